@@ -0,0 +1,187 @@
+use crate::cache::CachedToken;
+use crate::{
+    collect_scopes, create_jwt, default_headers, retry_after, AccessToken, Claims, Credentials,
+    Error, OAuthErrorResponse, TokenResponse, DEFAULT_TIMEOUT, GRANT_TYPE,
+};
+use futures::future::{self, Either, Future, Shared};
+use reqwest::r#async::{Client as HTTPClient, Response};
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_timer::Timeout;
+
+type TokenFuture = Box<dyn Future<Item = AccessToken, Error = Arc<Error>> + Send>;
+
+#[derive(Clone)]
+pub struct AsyncClient {
+    credentials: Credentials,
+    scopes: String,
+    token_url: String,
+    subject: Option<String>,
+    http: HTTPClient,
+    access_token: Arc<CachedToken>,
+    refresh: Arc<Mutex<Option<Shared<TokenFuture>>>>,
+}
+
+impl AsyncClient {
+    pub fn new(
+        credentials: Credentials,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> AsyncClient {
+        let http = HTTPClient::builder()
+            .connect_timeout(DEFAULT_TIMEOUT)
+            .timeout(DEFAULT_TIMEOUT)
+            .build()
+            .expect("failed to build HTTP client");
+        Self::with_http_client(credentials, scopes, http)
+    }
+
+    pub fn with_http_client(
+        credentials: Credentials,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+        http: HTTPClient,
+    ) -> AsyncClient {
+        AsyncClient {
+            token_url: credentials.token_uri().to_owned(),
+            credentials,
+            scopes: collect_scopes(scopes).join(" "),
+            subject: None,
+            http,
+            access_token: Arc::new(CachedToken::default()),
+            refresh: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn token_url(mut self, token_url: impl Into<String>) -> Self {
+        self.token_url = token_url.into();
+        self
+    }
+
+    pub fn subject(mut self, subject: impl Into<String>) -> Self {
+        self.subject = Some(subject.into());
+        self
+    }
+
+    pub fn refresh_margin(mut self, margin: Duration) -> Self {
+        self.access_token = Arc::new(CachedToken::new(margin));
+        self
+    }
+
+    /// Concurrent callers share one fetch. Dropping the returned future is
+    /// safe: the fetch carries on for the others, or for the next caller.
+    pub fn get_token(&self) -> impl Future<Item = AccessToken, Error = Error> + Send {
+        if let Some(token) = self.access_token.get() {
+            return Either::A(future::ok(token));
+        }
+
+        let mut refresh = self.refresh.lock().unwrap();
+        // A fetch may have finished since the check above.
+        if let Some(token) = self.access_token.get() {
+            return Either::A(future::ok(token));
+        }
+        let shared = match &*refresh {
+            Some(shared) => shared.clone(),
+            None => {
+                let access_token = self.access_token.clone();
+                let in_flight = self.refresh.clone();
+                let fetch: TokenFuture = Box::new(self.fetch_token().then(move |result| {
+                    // Cached first, so callers never find neither a token
+                    // nor a fetch to wait on.
+                    if let Ok(token) = &result {
+                        access_token.set(token.clone());
+                    }
+                    in_flight.lock().unwrap().take();
+                    result.map_err(Arc::new)
+                }));
+                let shared = fetch.shared();
+                *refresh = Some(shared.clone());
+                shared
+            }
+        };
+
+        Either::B(
+            shared
+                .map(|token| (*token).clone())
+                .map_err(|err| Error::Shared((*err).clone())),
+        )
+    }
+
+    /// Like `get_token`, but fails with `Error::DeadlineExceeded` once
+    /// `deadline` passes. Must run on a tokio runtime with a timer.
+    pub fn get_token_with_deadline(
+        &self,
+        deadline: Instant,
+    ) -> impl Future<Item = AccessToken, Error = Error> + Send {
+        Timeout::new_at(self.get_token(), deadline).map_err(|err| {
+            if err.is_elapsed() {
+                Error::DeadlineExceeded
+            } else if err.is_timer() {
+                let err = err.into_timer().unwrap();
+                Error::Io(io::Error::other(err))
+            } else {
+                err.into_inner().unwrap()
+            }
+        })
+    }
+
+    pub fn get_token_with_timeout(
+        &self,
+        timeout: Duration,
+    ) -> impl Future<Item = AccessToken, Error = Error> + Send {
+        self.get_token_with_deadline(Instant::now() + timeout)
+    }
+
+    pub fn invalidate(&self) {
+        self.access_token.invalidate();
+    }
+
+    fn fetch_token(&self) -> impl Future<Item = AccessToken, Error = Error> + Send {
+        let token = match self.create_jwt() {
+            Ok(token) => token,
+            Err(err) => return Either::A(future::err(err)),
+        };
+        let mut params = HashMap::new();
+        params.insert("grant_type", GRANT_TYPE);
+        params.insert("assertion", &token);
+
+        Either::B(
+            self.http
+                .post(&self.token_url)
+                .headers(default_headers())
+                .form(&params)
+                .send()
+                .from_err()
+                .and_then(parse_response)
+                .map(AccessToken::from),
+        )
+    }
+
+    fn create_jwt(&self) -> Result<String, Error> {
+        let claims = Claims {
+            scope: Some(self.scopes.clone()),
+            sub: self.subject.clone(),
+            ..Claims::new(&self.credentials, &self.token_url)?
+        };
+        create_jwt(&self.credentials, &claims)
+    }
+}
+
+fn parse_response(mut response: Response) -> impl Future<Item = TokenResponse, Error = Error> {
+    match response.error_for_status_ref().err() {
+        None => Either::A(response.json::<TokenResponse>().from_err()),
+        Some(_) => {
+            let status = response.status();
+            let retry_after = retry_after(response.headers());
+            Either::B(response.json::<OAuthErrorResponse>().then(move |response| {
+                Err(match response {
+                    Ok(response) => Error::from(response),
+                    Err(_) => Error::Status {
+                        status,
+                        retry_after,
+                    },
+                })
+            }))
+        }
+    }
+}
@@ -0,0 +1,283 @@
+#[cfg(feature = "ring")]
+use crate::encoding::hex;
+use crate::Error;
+#[cfg(feature = "ring")]
+use ring::{constant_time, digest, hmac};
+#[cfg(feature = "ring")]
+use serde::Serialize;
+#[cfg(feature = "ring")]
+use serde_json::Value;
+#[cfg(feature = "ring")]
+use std::io::{self, BufRead, Write};
+use std::net::SocketAddr;
+#[cfg(feature = "ring")]
+use std::sync::Mutex;
+#[cfg(feature = "ring")]
+use std::time::UNIX_EPOCH;
+use std::time::{Duration, SystemTime};
+
+/// Receives a record of every token request, for compliance logging.
+pub trait AuditLog: Send + Sync {
+    fn record(&self, entry: &AuditEntry);
+}
+
+/// One token request, with any retries counted in `latency`.
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub timestamp: SystemTime,
+    pub client_email: String,
+    /// The user impersonated through domain-wide delegation.
+    pub subject: Option<String>,
+    /// Set for access tokens.
+    pub scopes: Option<String>,
+    /// Set for ID tokens.
+    pub audience: Option<String>,
+    pub token_url: String,
+    /// The address the token endpoint answered from, when known.
+    pub remote_addr: Option<SocketAddr>,
+    pub outcome: AuditOutcome,
+    pub latency: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditOutcome {
+    Success,
+    /// `kind` is the error's [`Error::kind`].
+    Failure {
+        kind: &'static str,
+        message: String,
+    },
+}
+
+impl AuditOutcome {
+    pub(crate) fn new<T>(result: &Result<T, Error>) -> Self {
+        match result {
+            Ok(_) => AuditOutcome::Success,
+            Err(err) => AuditOutcome::Failure {
+                kind: err.kind(),
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "ring")]
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp_ms: u128,
+    client_email: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subject: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scopes: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audience: Option<&'a str>,
+    token_url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote_addr: Option<String>,
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_kind: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+    latency_ms: u128,
+    prev_hash: &'a str,
+}
+
+#[cfg(feature = "ring")]
+impl AuditEntry {
+    fn record<'a>(&'a self, prev_hash: &'a str) -> Record<'a> {
+        let (outcome, error_kind, error) = match &self.outcome {
+            AuditOutcome::Success => ("success", None, None),
+            AuditOutcome::Failure { kind, message } => {
+                ("failure", Some(*kind), Some(message.as_str()))
+            }
+        };
+        Record {
+            timestamp_ms: self
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+            client_email: &self.client_email,
+            subject: self.subject.as_deref(),
+            scopes: self.scopes.as_deref(),
+            audience: self.audience.as_deref(),
+            token_url: &self.token_url,
+            remote_addr: self.remote_addr.map(|addr| addr.to_string()),
+            outcome,
+            error_kind,
+            error,
+            latency_ms: self.latency.as_millis(),
+            prev_hash,
+        }
+    }
+}
+
+/// Writes entries as JSON lines, each holding an HMAC-SHA256, under `key`,
+/// of the line before. Without the key, changing, inserting or removing a
+/// line breaks the chain that [`verify_audit_log`] checks, except for
+/// dropping lines from the end: to catch that, keep the last hash
+/// somewhere the log's writer cannot change and compare it with the one
+/// `verify_audit_log` returns.
+#[cfg(feature = "ring")]
+pub struct ChainedAuditLog<W> {
+    key: hmac::SigningKey,
+    state: Mutex<(W, String)>,
+}
+
+#[cfg(feature = "ring")]
+impl<W: Write + Send> ChainedAuditLog<W> {
+    pub fn new(writer: W, key: &[u8]) -> Self {
+        Self::resume(writer, key, GENESIS_HASH)
+    }
+
+    /// Continues a chain whose last line hashed to `last_hash`, e.g. after
+    /// reopening the file in append mode. See [`verify_audit_log`].
+    pub fn resume(writer: W, key: &[u8], last_hash: impl Into<String>) -> Self {
+        ChainedAuditLog {
+            key: hmac::SigningKey::new(&digest::SHA256, key),
+            state: Mutex::new((writer, last_hash.into())),
+        }
+    }
+
+    fn write(&self, entry: &AuditEntry) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let (writer, prev) = &mut *state;
+        let line = serde_json::to_string(&entry.record(prev))?;
+        writeln!(writer, "{}", line)?;
+        writer.flush()?;
+        *prev = line_hash(&self.key, &line);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "ring")]
+impl<W: Write + Send> AuditLog for ChainedAuditLog<W> {
+    fn record(&self, entry: &AuditEntry) {
+        if let Err(err) = self.write(entry) {
+            log_warn!("failed to write audit log entry: {}", err);
+        }
+    }
+}
+
+/// Checks every line of a [`ChainedAuditLog`] written with `key`, returning
+/// the hash of the last line, or an error naming the first line that does
+/// not follow.
+#[cfg(feature = "ring")]
+pub fn verify_audit_log(reader: impl BufRead, key: &[u8]) -> Result<String, Error> {
+    let key = hmac::SigningKey::new(&digest::SHA256, key);
+    let mut prev = GENESIS_HASH.to_owned();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let record: Value = serde_json::from_str(&line)?;
+        let prev_hash = record["prev_hash"].as_str().unwrap_or_default();
+        if constant_time::verify_slices_are_equal(prev_hash.as_bytes(), prev.as_bytes()).is_err() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("audit log chain broken at line {}", index + 1),
+            )));
+        }
+        prev = line_hash(&key, &line);
+    }
+    Ok(prev)
+}
+
+#[cfg(feature = "ring")]
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[cfg(feature = "ring")]
+fn line_hash(key: &hmac::SigningKey, line: &str) -> String {
+    hex(hmac::sign(key, line.as_bytes()).as_ref())
+}
+
+#[cfg(all(test, feature = "ring"))]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const KEY: &[u8] = b"audit key";
+
+    fn entry(n: u64) -> AuditEntry {
+        AuditEntry {
+            timestamp: UNIX_EPOCH + Duration::from_secs(n),
+            client_email: "client@example.com".to_owned(),
+            subject: None,
+            scopes: Some("scope".to_owned()),
+            audience: None,
+            token_url: "https://oauth2.googleapis.com/token".to_owned(),
+            remote_addr: None,
+            outcome: AuditOutcome::Success,
+            latency: Duration::from_millis(n),
+        }
+    }
+
+    fn write_log(entries: u64) -> (Vec<String>, String) {
+        let log = ChainedAuditLog::new(Vec::new(), KEY);
+        let mut last = GENESIS_HASH.to_owned();
+        for n in 0..entries {
+            log.record(&entry(n));
+            last = log.state.lock().unwrap().1.clone();
+        }
+        let (output, _) = log.state.into_inner().unwrap();
+        let lines = String::from_utf8(output).unwrap();
+        (lines.lines().map(str::to_owned).collect(), last)
+    }
+
+    fn verify(lines: &[String], key: &[u8]) -> Result<String, Error> {
+        verify_audit_log(Cursor::new(lines.join("\n")), key)
+    }
+
+    fn broken_at(result: Result<String, Error>) -> String {
+        match result {
+            Err(Error::Io(err)) => err.to_string(),
+            other => panic!("expected a broken chain, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn verifies_chains() {
+        let (lines, last) = write_log(3);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(verify(&lines, KEY).unwrap(), last);
+
+        // Resuming continues the same chain.
+        let log = ChainedAuditLog::resume(Vec::new(), KEY, last);
+        log.record(&entry(3));
+        let (output, _) = log.state.into_inner().unwrap();
+        let mut resumed = lines.clone();
+        resumed.push(String::from_utf8(output).unwrap().trim_end().to_owned());
+        assert!(verify(&resumed, KEY).is_ok());
+    }
+
+    #[test]
+    fn detects_changed_and_dropped_lines() {
+        let (lines, last) = write_log(3);
+
+        let mut changed = lines.clone();
+        changed[1] = changed[1].replace("client@example.com", "other@example.com");
+        assert_eq!(
+            broken_at(verify(&changed, KEY)),
+            "audit log chain broken at line 3"
+        );
+
+        let mut dropped = lines.clone();
+        dropped.remove(0);
+        assert_eq!(
+            broken_at(verify(&dropped, KEY)),
+            "audit log chain broken at line 1"
+        );
+
+        // Dropping trailing lines shows up in the last hash.
+        assert_ne!(verify(&lines[..2], KEY).unwrap(), last);
+    }
+
+    #[test]
+    fn chains_cannot_be_rebuilt_without_the_key() {
+        let (lines, _) = write_log(2);
+        assert_eq!(
+            broken_at(verify(&lines, b"other key")),
+            "audit log chain broken at line 2"
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use crate::{default_http_client, Error, TokenProvider};
+use reqwest::{Client as HTTPClient, Request, Response, StatusCode};
+
+/// Wraps an HTTP client so every request carries a token from `provider`.
+///
+/// A request rejected with `401 Unauthorized` is retried once with a freshly
+/// fetched token, provided its body can be cloned.
+pub struct AuthorizedClient<P> {
+    http: HTTPClient,
+    provider: P,
+}
+
+impl<P: TokenProvider> AuthorizedClient<P> {
+    pub fn new(provider: P) -> Self {
+        Self::with_http_client(provider, default_http_client())
+    }
+
+    pub fn with_http_client(provider: P, http: HTTPClient) -> Self {
+        AuthorizedClient { http, provider }
+    }
+
+    pub fn http_client(&self) -> &HTTPClient {
+        &self.http
+    }
+
+    pub fn provider(&self) -> &P {
+        &self.provider
+    }
+
+    pub fn execute(&self, request: Request) -> Result<Response, Error> {
+        let retry = request.try_clone();
+        let response = self.send(request)?;
+        match retry {
+            Some(request) if response.status() == StatusCode::UNAUTHORIZED => {
+                self.provider.invalidate();
+                self.send(request)
+            }
+            _ => Ok(response),
+        }
+    }
+
+    fn send(&self, mut request: Request) -> Result<Response, Error> {
+        self.provider.authorize(request.headers_mut())?;
+        Ok(self.http.execute(request)?)
+    }
+}
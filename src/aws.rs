@@ -0,0 +1,309 @@
+use crate::encoding::{self, hex, percent_encode};
+use crate::{Error, SubjectTokenSupplier};
+use reqwest::{Client as HTTPClient, Url};
+use ring::{digest, hmac};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::time::SystemTime;
+
+const ECS_CREDENTIALS_HOST: &str = "http://169.254.170.2";
+const IMDSV2_TTL_HEADER: &str = "x-aws-ec2-metadata-token-ttl-seconds";
+const IMDSV2_TOKEN_HEADER: &str = "x-aws-ec2-metadata-token";
+const SIGNING_ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const TARGET_RESOURCE_HEADER: &str = "x-goog-cloud-target-resource";
+
+/// Supplies subject tokens for AWS workloads, built from the AWS fields of an
+/// external account `credential_source`.
+pub(crate) struct AwsTokenSupplier {
+    pub(crate) region_url: Option<String>,
+    pub(crate) credentials_url: Option<String>,
+    pub(crate) verification_url: String,
+    pub(crate) imdsv2_session_token_url: Option<String>,
+    pub(crate) audience: String,
+    pub(crate) http: HTTPClient,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SignedRequest<'a> {
+    url: &'a str,
+    method: &'static str,
+    headers: Vec<SignedHeader<'a>>,
+}
+
+#[derive(Serialize)]
+struct SignedHeader<'a> {
+    key: &'a str,
+    value: &'a str,
+}
+
+impl SubjectTokenSupplier for AwsTokenSupplier {
+    /// A GetCallerIdentity request signed with the instance's credentials,
+    /// which STS replays to verify it.
+    fn subject_token(&self) -> Result<String, Error> {
+        subject_token(self)
+    }
+}
+
+fn subject_token(source: &AwsTokenSupplier) -> Result<String, Error> {
+    let http = &source.http;
+    let session_token = match &source.imdsv2_session_token_url {
+        Some(url) if credentials_from_env().is_none() || region_from_env().is_none() => Some(
+            http.put(url)
+                .header(IMDSV2_TTL_HEADER, "300")
+                .send()?
+                .error_for_status()?
+                .text()?,
+        ),
+        _ => None,
+    };
+    let metadata_get = |url: &str| -> Result<String, Error> {
+        let mut request = http.get(url);
+        if let Some(token) = &session_token {
+            request = request.header(IMDSV2_TOKEN_HEADER, token.as_str());
+        }
+        Ok(request.send()?.error_for_status()?.text()?)
+    };
+
+    let region = match region_from_env() {
+        Some(region) => region,
+        None => {
+            let url = source
+                .region_url
+                .as_deref()
+                .ok_or_else(|| Error::InvalidCredentials("AWS region_url is missing".to_owned()))?;
+            // The endpoint returns the availability zone, e.g. us-east-2b.
+            let mut zone = metadata_get(url)?;
+            zone.pop();
+            zone
+        }
+    };
+
+    let credentials = match credentials_from_env() {
+        Some(credentials) => credentials,
+        None => match env::var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI") {
+            Ok(uri) => {
+                serde_json::from_str(&metadata_get(&format!("{}{}", ECS_CREDENTIALS_HOST, uri))?)?
+            }
+            Err(_) => {
+                let url = source.credentials_url.as_deref().ok_or_else(|| {
+                    Error::InvalidCredentials("AWS credentials url is missing".to_owned())
+                })?;
+                let role = metadata_get(url)?;
+                let url = format!("{}/{}", url.trim_end_matches('/'), role.trim());
+                serde_json::from_str(&metadata_get(&url)?)?
+            }
+        },
+    };
+
+    let url = source.verification_url.replace("{region}", &region);
+    sign_caller_identity(
+        &url,
+        &region,
+        &credentials,
+        &source.audience,
+        SystemTime::now(),
+    )
+}
+
+fn region_from_env() -> Option<String> {
+    env::var("AWS_REGION")
+        .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+        .ok()
+}
+
+fn credentials_from_env() -> Option<AwsCredentials> {
+    Some(AwsCredentials {
+        access_key_id: env::var("AWS_ACCESS_KEY_ID").ok()?,
+        secret_access_key: env::var("AWS_SECRET_ACCESS_KEY").ok()?,
+        token: env::var("AWS_SESSION_TOKEN").ok(),
+    })
+}
+
+fn sign_caller_identity(
+    url: &str,
+    region: &str,
+    credentials: &AwsCredentials,
+    audience: &str,
+    now: SystemTime,
+) -> Result<String, Error> {
+    let parsed = Url::parse(url)
+        .map_err(|_| Error::InvalidCredentials(format!("invalid AWS url {:?}", url)))?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| Error::InvalidCredentials(format!("invalid AWS url {:?}", url)))?;
+    let (_, amz_date) = encoding::timestamps(now)?;
+
+    let mut headers = vec![
+        ("host", host),
+        ("x-amz-date", amz_date.as_str()),
+        (TARGET_RESOURCE_HEADER, audience),
+    ];
+    if let Some(token) = &credentials.token {
+        headers.push(("x-amz-security-token", token.as_str()));
+    }
+    headers.sort();
+    let authorization = authorization(&parsed, &headers, credentials, &amz_date, region, "sts");
+
+    let mut signed = SignedRequest {
+        url,
+        method: "POST",
+        headers: vec![SignedHeader {
+            key: "Authorization",
+            value: &authorization,
+        }],
+    };
+    signed.headers.extend(
+        headers
+            .iter()
+            .map(|&(key, value)| SignedHeader { key, value }),
+    );
+    Ok(percent_encode(&serde_json::to_string(&signed)?))
+}
+
+/// The SigV4 `Authorization` header for a POST of `url` with an empty body.
+/// `headers` must be sorted, with lowercase names.
+fn authorization(
+    url: &Url,
+    headers: &[(&str, &str)],
+    credentials: &AwsCredentials,
+    amz_date: &str,
+    region: &str,
+    service: &str,
+) -> String {
+    let date = &amz_date[..8];
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let mut query: Vec<&str> = url.query().unwrap_or("").split('&').collect();
+    query.sort();
+    let canonical_request = format!(
+        "POST\n{}\n{}\n{}\n{}\n{}",
+        url.path(),
+        query.join("&"),
+        canonical_headers,
+        signed_headers,
+        hex(digest::digest(&digest::SHA256, b"").as_ref())
+    );
+
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        SIGNING_ALGORITHM,
+        amz_date,
+        scope,
+        hex(digest::digest(&digest::SHA256, canonical_request.as_bytes()).as_ref())
+    );
+    let mut key = format!("AWS4{}", credentials.secret_access_key).into_bytes();
+    for part in &[date, region, service, "aws4_request"] {
+        key = hmac_sha256(&key, part.as_bytes());
+    }
+    let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+    format!(
+        "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+        SIGNING_ALGORITHM, credentials.access_key_id, scope, signed_headers, signature
+    )
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let key = hmac::SigningKey::new(&digest::SHA256, key);
+    hmac::sign(&key, data).as_ref().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // The credentials, date and region shared by the AWS SigV4 test suite.
+    const AMZ_DATE: &str = "20150830T123600Z";
+
+    fn credentials(token: Option<&str>) -> AwsCredentials {
+        AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_owned(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned(),
+            token: token.map(str::to_owned),
+        }
+    }
+
+    fn suite_authorization(url: &str) -> String {
+        let headers = [("host", "example.amazonaws.com"), ("x-amz-date", AMZ_DATE)];
+        authorization(
+            &Url::parse(url).unwrap(),
+            &headers,
+            &credentials(None),
+            AMZ_DATE,
+            "us-east-1",
+            "service",
+        )
+    }
+
+    #[test]
+    fn signs_post_vanilla() {
+        assert_eq!(
+            suite_authorization("https://example.amazonaws.com/"),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5da7c1a2acd57cee7505fc6676e4e544621c30862966e37dddb68e92efbe5d6b"
+        );
+    }
+
+    #[test]
+    fn signs_post_vanilla_query() {
+        assert_eq!(
+            suite_authorization("https://example.amazonaws.com/?Param1=value1"),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=28038455d6de14eafc1f9222cf5aa6f1a96197d7deb8263271d420d138af7f11"
+        );
+    }
+
+    #[test]
+    fn signs_get_caller_identity() {
+        let url = "https://sts.us-east-1.amazonaws.com?Action=GetCallerIdentity&Version=2011-06-15";
+        let audience =
+            "//iam.googleapis.com/projects/1/locations/global/workloadIdentityPools/p/providers/aws";
+        let token = sign_caller_identity(
+            url,
+            "us-east-1",
+            &credentials(Some("session")),
+            audience,
+            UNIX_EPOCH + Duration::from_secs(1_440_938_160),
+        )
+        .unwrap();
+        let expected = json!({
+            "url": url,
+            "method": "POST",
+            "headers": [
+                {
+                    "key": "Authorization",
+                    "value": "AWS4-HMAC-SHA256 \
+                        Credential=AKIDEXAMPLE/20150830/us-east-1/sts/aws4_request, \
+                        SignedHeaders=host;x-amz-date;x-amz-security-token;x-goog-cloud-target-resource, \
+                        Signature=f0500c6e1a6586a1bdcb2cbedd6d914576082cee8b68bf9d8f5e270b189a86c9",
+                },
+                { "key": "host", "value": "sts.us-east-1.amazonaws.com" },
+                { "key": "x-amz-date", "value": AMZ_DATE },
+                { "key": "x-amz-security-token", "value": "session" },
+                { "key": "x-goog-cloud-target-resource", "value": audience },
+            ],
+        });
+        let token = Url::parse(&format!("http://localhost/?token={}", token)).unwrap();
+        let (_, token) = token.query_pairs().next().unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&token).unwrap(), expected);
+    }
+}
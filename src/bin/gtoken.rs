@@ -0,0 +1,111 @@
+//! Prints an access token or ID token from the application default
+//! credentials, like `gcloud auth print-access-token`.
+
+use google_token_provider::{application_default, application_default_id_token, Error, Scope};
+use serde_json::json;
+use std::env;
+use std::process;
+use std::time::UNIX_EPOCH;
+
+const USAGE: &str = "\
+Usage: gtoken [OPTIONS]
+
+Prints an access token from the application default credentials.
+
+Options:
+    --scopes SCOPES     Comma separated scopes to request. Short names
+                        expand to https://www.googleapis.com/auth/NAME.
+                        Defaults to cloud-platform.
+    --audience AUD      Print an ID token for AUD instead
+    --json              Print the token and its expiry as JSON
+    -h, --help          Print this message
+    -V, --version       Print the version";
+
+const SCOPE_PREFIX: &str = "https://www.googleapis.com/auth/";
+
+struct Options {
+    scopes: Vec<String>,
+    audience: Option<String>,
+    json: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        scopes: vec![Scope::CloudPlatform.as_str().to_owned()],
+        audience: None,
+        json: false,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("{} requires a value", name))
+        };
+        match arg.as_str() {
+            "--scopes" => {
+                options.scopes = value("--scopes")?
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|scope| !scope.is_empty())
+                    .map(expand_scope)
+                    .collect();
+            }
+            "--audience" => options.audience = Some(value("--audience")?),
+            "--json" => options.json = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "-V" | "--version" => {
+                println!("gtoken {}", env!("CARGO_PKG_VERSION"));
+                process::exit(0);
+            }
+            _ => return Err(format!("unexpected argument {:?}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn expand_scope(scope: &str) -> String {
+    if scope.contains("://") {
+        scope.to_owned()
+    } else {
+        format!("{}{}", SCOPE_PREFIX, scope)
+    }
+}
+
+fn run(options: &Options) -> Result<String, Error> {
+    let token = match &options.audience {
+        Some(audience) => application_default_id_token(audience)?,
+        None => application_default(&options.scopes)?.get_token()?,
+    };
+    if !options.json {
+        return Ok(token.value.clone());
+    }
+    let field = match options.audience {
+        Some(_) => "id_token",
+        None => "access_token",
+    };
+    Ok(json!({
+        field: token.value,
+        "expires_in": token.expires_in().as_secs(),
+        "expiry": token.expires.duration_since(UNIX_EPOCH)?.as_secs(),
+    })
+    .to_string())
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("gtoken: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+    match run(&options) {
+        Ok(output) => println!("{}", output),
+        Err(err) => {
+            eprintln!("gtoken: {}", err);
+            process::exit(1);
+        }
+    }
+}
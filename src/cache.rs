@@ -0,0 +1,427 @@
+use crate::{AccessToken, Clock, Error, SystemClock, TokenObserver};
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+pub(crate) const DEFAULT_REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+const STALE_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+pub(crate) const DEFAULT_FAILURE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(crate) struct CachedToken {
+    token: RwLock<Option<AccessToken>>,
+    refresh: Mutex<()>,
+    refresh_margin: Duration,
+}
+
+impl Default for CachedToken {
+    fn default() -> Self {
+        CachedToken::new(DEFAULT_REFRESH_MARGIN)
+    }
+}
+
+impl CachedToken {
+    pub(crate) fn new(refresh_margin: Duration) -> Self {
+        CachedToken {
+            token: RwLock::new(None),
+            refresh: Mutex::new(()),
+            refresh_margin,
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<AccessToken> {
+        match &*self.token.read().unwrap() {
+            Some(token) if !token.expires_within(self.refresh_margin) => Some(token.clone()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn set(&self, token: AccessToken) {
+        *self.token.write().unwrap() = Some(token);
+    }
+
+    pub(crate) fn invalidate(&self) {
+        *self.token.write().unwrap() = None;
+    }
+
+    pub(crate) fn refresh(
+        &self,
+        fetch: impl FnOnce() -> Result<AccessToken, Error>,
+    ) -> Result<AccessToken, Error> {
+        let _refresh = self.refresh.lock().unwrap();
+        let token = fetch()?;
+        self.set(token.clone());
+        Ok(token)
+    }
+
+    pub(crate) fn get_or_fetch(
+        &self,
+        fetch: impl FnOnce() -> Result<AccessToken, Error>,
+    ) -> Result<AccessToken, Error> {
+        if let Some(token) = self.get() {
+            return Ok(token);
+        }
+
+        // Only one caller refreshes at a time, the rest pick up its token
+        let _refresh = self.refresh.lock().unwrap();
+        if let Some(token) = self.get() {
+            return Ok(token);
+        }
+
+        let token = fetch()?;
+        self.set(token.clone());
+        Ok(token)
+    }
+}
+
+/// Storage for access tokens, keyed by the scopes or audience they were
+/// issued for. Implementations must be safe to share between clients.
+pub trait TokenCache: Send + Sync {
+    fn get(&self, key: &str) -> Option<AccessToken>;
+
+    fn put(&self, key: &str, token: AccessToken);
+
+    fn invalidate(&self, key: &str);
+}
+
+/// The default in-process [`TokenCache`].
+#[derive(Default)]
+pub struct MemoryTokenCache {
+    tokens: RwLock<HashMap<String, AccessToken>>,
+}
+
+impl MemoryTokenCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TokenCache for MemoryTokenCache {
+    fn get(&self, key: &str) -> Option<AccessToken> {
+        self.tokens.read().unwrap().get(key).cloned()
+    }
+
+    fn put(&self, key: &str, token: AccessToken) {
+        self.tokens.write().unwrap().insert(key.to_owned(), token);
+    }
+
+    fn invalidate(&self, key: &str) {
+        self.tokens.write().unwrap().remove(key);
+    }
+}
+
+struct Failure {
+    at: Instant,
+    retry_after: Option<Duration>,
+}
+
+impl Failure {
+    fn new(err: &Error) -> Self {
+        Failure {
+            at: Instant::now(),
+            retry_after: retry_after(err),
+        }
+    }
+
+    /// How much longer to wait before another request, given a minimum
+    /// `interval` between attempts.
+    fn remaining(&self, interval: Duration) -> Option<Duration> {
+        let wait = cmp::max(interval, self.retry_after.unwrap_or_default());
+        wait.checked_sub(self.at.elapsed())
+            .filter(|remaining| *remaining > Duration::from_secs(0))
+    }
+}
+
+fn retry_after(err: &Error) -> Option<Duration> {
+    match err {
+        Error::Status { retry_after, .. } => *retry_after,
+        Error::Shared(err) => retry_after(err),
+        _ => None,
+    }
+}
+
+pub(crate) struct CachedTokens {
+    store: Arc<dyn TokenCache>,
+    namespace: String,
+    // Per key refresh locks, guarding the last failed refresh
+    refreshes: Mutex<HashMap<String, Arc<Mutex<Option<Failure>>>>>,
+    // Every key handed out, including tokens that were already in the store
+    served: RwLock<HashSet<String>>,
+    refresh_margin: Duration,
+    failure_interval: Duration,
+    clock: Arc<dyn Clock>,
+    serve_stale: bool,
+    observer: Option<Arc<dyn TokenObserver>>,
+}
+
+impl Default for CachedTokens {
+    fn default() -> Self {
+        CachedTokens::new(DEFAULT_REFRESH_MARGIN)
+    }
+}
+
+impl CachedTokens {
+    pub(crate) fn new(refresh_margin: Duration) -> Self {
+        Self::with_store(
+            Arc::new(MemoryTokenCache::new()),
+            String::new(),
+            refresh_margin,
+            Arc::new(SystemClock),
+        )
+    }
+
+    /// Keys are prefixed with `namespace` so clients can share one store.
+    pub(crate) fn with_store(
+        store: Arc<dyn TokenCache>,
+        namespace: String,
+        refresh_margin: Duration,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        CachedTokens {
+            store,
+            namespace,
+            refreshes: Mutex::new(HashMap::new()),
+            served: RwLock::new(HashSet::new()),
+            refresh_margin,
+            failure_interval: DEFAULT_FAILURE_INTERVAL,
+            clock,
+            serve_stale: false,
+            observer: None,
+        }
+    }
+
+    pub(crate) fn observer(mut self, observer: Option<Arc<dyn TokenObserver>>) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    /// The minimum time between a failed refresh and the next attempt,
+    /// extended by any `Retry-After` the endpoint sent.
+    pub(crate) fn failure_interval(mut self, interval: Duration) -> Self {
+        self.failure_interval = interval;
+        self
+    }
+
+    /// When a refresh fails, keep handing out the cached token until it has
+    /// actually expired, retrying the refresh at most every few seconds.
+    pub(crate) fn serve_stale(mut self, serve_stale: bool) -> Self {
+        self.serve_stale = serve_stale;
+        self
+    }
+
+    pub(crate) fn get_or_fetch(
+        &self,
+        key: &str,
+        fetch: impl FnOnce() -> Result<AccessToken, Error>,
+    ) -> Result<AccessToken, Error> {
+        let key = self.key(key);
+        if let Some(token) = self.get(&key) {
+            log_debug!("token cache hit for {}", key);
+            self.mark_served(&key);
+            self.notify(|observer| observer.cache_hit());
+            return Ok(token);
+        }
+        log_debug!("token cache miss for {}", key);
+        self.notify(|observer| observer.cache_miss());
+
+        // Only one caller refreshes at a time, the rest pick up its token
+        self.mark_served(&key);
+        let refresh = self.refresh_lock(&key);
+        let mut last_failure = refresh.lock().unwrap();
+        if let Some(token) = self.get(&key) {
+            return Ok(token);
+        }
+
+        let now = self.clock.now();
+        if let Some(expired) = self
+            .store
+            .get(&key)
+            .filter(|token| token.expired() || token.expired_at(now))
+        {
+            // Dropped so the event fires once per expired token.
+            self.store.invalidate(&key);
+            self.notify(|observer| observer.token_expired(&expired));
+        }
+
+        let stale = if self.serve_stale {
+            self.store
+                .get(&key)
+                .filter(|token| !token.expired() && !token.expired_at(now))
+        } else {
+            None
+        };
+        if let Some(failure) = &*last_failure {
+            match &stale {
+                Some(stale) if failure.remaining(STALE_RETRY_INTERVAL).is_some() => {
+                    log_debug!("serving stale token for {}", key);
+                    return Ok(stale.clone());
+                }
+                None => {
+                    if let Some(retry_in) = failure.remaining(self.failure_interval) {
+                        return Err(Error::Throttled { retry_in });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        match fetch() {
+            Ok(token) => {
+                *last_failure = None;
+                self.store.put(&key, token.clone());
+                self.notify(|observer| observer.token_refreshed(&token));
+                Ok(token)
+            }
+            Err(err) => {
+                *last_failure = Some(Failure::new(&err));
+                self.notify(|observer| observer.refresh_failed(&err, stale.is_some()));
+                if stale.is_some() {
+                    log_warn!("token refresh failed, serving stale token: {}", err);
+                }
+                stale.ok_or(err)
+            }
+        }
+    }
+
+    pub(crate) fn refresh(
+        &self,
+        key: &str,
+        fetch: impl FnOnce() -> Result<AccessToken, Error>,
+    ) -> Result<AccessToken, Error> {
+        let key = self.key(key);
+        self.mark_served(&key);
+        let refresh = self.refresh_lock(&key);
+        let mut last_failure = refresh.lock().unwrap();
+        let token = match fetch() {
+            Ok(token) => token,
+            Err(err) => {
+                *last_failure = Some(Failure::new(&err));
+                self.notify(|observer| observer.refresh_failed(&err, false));
+                return Err(err);
+            }
+        };
+        *last_failure = None;
+        self.store.put(&key, token.clone());
+        self.notify(|observer| observer.token_refreshed(&token));
+        Ok(token)
+    }
+
+    /// Drops every token this cache has handed out, wherever the store got
+    /// them from.
+    pub(crate) fn invalidate(&self) {
+        for key in self.served.read().unwrap().iter() {
+            self.store.invalidate(key);
+        }
+    }
+
+    /// Drops the token for `key`, even if it was never handed out by this
+    /// cache, e.g. one persisted by an earlier process.
+    pub(crate) fn invalidate_key(&self, key: &str) {
+        self.store.invalidate(&self.key(key));
+    }
+
+    fn get(&self, key: &str) -> Option<AccessToken> {
+        let now = self.clock.now();
+        self.store.get(key).filter(|token| {
+            !token.expires_within(self.refresh_margin)
+                && !token.expires_within_at(self.refresh_margin, now)
+        })
+    }
+
+    fn notify(&self, event: impl FnOnce(&dyn TokenObserver)) {
+        if let Some(observer) = &self.observer {
+            event(observer.as_ref());
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.namespace, key)
+    }
+
+    fn mark_served(&self, key: &str) {
+        if !self.served.read().unwrap().contains(key) {
+            self.served.write().unwrap().insert(key.to_owned());
+        }
+    }
+
+    fn refresh_lock(&self, key: &str) -> Arc<Mutex<Option<Failure>>> {
+        self.refreshes
+            .lock()
+            .unwrap()
+            .entry(key.to_owned())
+            .or_default()
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockClock;
+    use std::cell::Cell;
+    use std::time::SystemTime;
+
+    fn token(value: &str, lifetime: u64) -> AccessToken {
+        AccessToken::new(value, SystemTime::now() + Duration::from_secs(lifetime))
+    }
+
+    fn cache(store: Arc<dyn TokenCache>, clock: Arc<dyn Clock>) -> CachedTokens {
+        CachedTokens::with_store(store, "ns/".to_owned(), Duration::from_secs(60), clock)
+    }
+
+    #[test]
+    fn caches_until_refresh_margin() {
+        let clock = Arc::new(MockClock::new(SystemTime::now()));
+        let tokens = cache(Arc::new(MemoryTokenCache::new()), clock.clone());
+        let fetches = Cell::new(0);
+        let fetch = || {
+            fetches.set(fetches.get() + 1);
+            Ok(token(&format!("t{}", fetches.get()), 3600))
+        };
+
+        assert_eq!(tokens.get_or_fetch("a", fetch).unwrap().value, "t1");
+        assert_eq!(tokens.get_or_fetch("a", fetch).unwrap().value, "t1");
+        clock.advance(Duration::from_secs(3600 - 61));
+        assert_eq!(tokens.get_or_fetch("a", fetch).unwrap().value, "t1");
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(tokens.get_or_fetch("a", fetch).unwrap().value, "t2");
+        assert_eq!(fetches.get(), 2);
+    }
+
+    #[test]
+    fn invalidate_drops_tokens_served_from_store() {
+        let store = Arc::new(MemoryTokenCache::new());
+        store.put("ns/a", token("persisted", 3600));
+        let tokens = cache(store.clone(), Arc::new(SystemClock));
+
+        let unused = || -> Result<AccessToken, Error> { panic!("unexpected fetch") };
+        assert_eq!(tokens.get_or_fetch("a", unused).unwrap().value, "persisted");
+        tokens.invalidate();
+        assert!(store.get("ns/a").is_none());
+    }
+
+    #[test]
+    fn invalidate_key_drops_unserved_tokens() {
+        let store = Arc::new(MemoryTokenCache::new());
+        store.put("ns/a", token("persisted", 3600));
+        let tokens = cache(store.clone(), Arc::new(SystemClock));
+
+        tokens.invalidate_key("a");
+        assert!(store.get("ns/a").is_none());
+    }
+
+    #[test]
+    fn failed_refresh_is_throttled() {
+        let tokens = cache(Arc::new(MemoryTokenCache::new()), Arc::new(SystemClock))
+            .failure_interval(Duration::from_secs(60));
+        let fail = || Err(Error::InvalidToken("nope".to_owned()));
+        assert!(matches!(
+            tokens.get_or_fetch("a", fail),
+            Err(Error::InvalidToken(_))
+        ));
+        assert!(matches!(
+            tokens.get_or_fetch("a", || Ok(token("t", 3600))),
+            Err(Error::Throttled { .. })
+        ));
+    }
+}
@@ -0,0 +1,234 @@
+use crate::Error;
+use std::fmt;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+type StateListener = Box<dyn Fn(CircuitState) + Send + Sync>;
+
+/// Stops calling the token endpoint after repeated transient failures.
+///
+/// After `failure_threshold` consecutive failures the circuit opens and
+/// requests fail immediately with [`Error::CircuitOpen`]. Once `cool_down` has
+/// passed a single trial request is let through; its outcome closes or
+/// re-opens the circuit.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cool_down: Duration,
+    state: Mutex<BreakerState>,
+    listener: Option<StateListener>,
+}
+
+struct BreakerState {
+    state: CircuitState,
+    failures: u32,
+    opened_at: Instant,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cool_down: Duration) -> Self {
+        CircuitBreaker {
+            failure_threshold: failure_threshold.max(1),
+            cool_down,
+            state: Mutex::new(BreakerState {
+                state: CircuitState::Closed,
+                failures: 0,
+                opened_at: Instant::now(),
+            }),
+            listener: None,
+        }
+    }
+
+    /// Called with the new state whenever the circuit changes state.
+    pub fn on_state_change(
+        mut self,
+        listener: impl Fn(CircuitState) + Send + Sync + 'static,
+    ) -> Self {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.state.lock().unwrap().state
+    }
+
+    pub(crate) fn call<T>(&self, f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+        let trial = {
+            let mut state = self.state.lock().unwrap();
+            match state.state {
+                CircuitState::Closed => false,
+                CircuitState::Open => {
+                    if state.opened_at.elapsed() < self.cool_down {
+                        return Err(Error::CircuitOpen);
+                    }
+                    state.state = CircuitState::HalfOpen;
+                    true
+                }
+                // A trial request is already in flight
+                CircuitState::HalfOpen => return Err(Error::CircuitOpen),
+            }
+        };
+        if trial {
+            self.notify(Some(CircuitState::HalfOpen));
+        }
+
+        let mut guard = TrialGuard {
+            breaker: self,
+            armed: trial,
+        };
+        let result = f();
+        guard.armed = false;
+
+        // Listeners run after the lock is released, so they may inspect the
+        // breaker.
+        let changed = {
+            let mut state = self.state.lock().unwrap();
+            match &result {
+                Err(err) if err.is_retryable() => {
+                    state.failures += 1;
+                    let trial = state.state == CircuitState::HalfOpen;
+                    if trial || state.failures >= self.failure_threshold {
+                        state.open()
+                    } else {
+                        None
+                    }
+                }
+                _ => {
+                    state.failures = 0;
+                    state.transition(CircuitState::Closed)
+                }
+            }
+        };
+        self.notify(changed);
+        result
+    }
+
+    fn notify(&self, changed: Option<CircuitState>) {
+        if let (Some(listener), Some(state)) = (&self.listener, changed) {
+            listener(state);
+        }
+    }
+}
+
+impl BreakerState {
+    /// Moves to `next`, returning it if the state changed.
+    fn transition(&mut self, next: CircuitState) -> Option<CircuitState> {
+        if self.state == next {
+            return None;
+        }
+        self.state = next;
+        Some(next)
+    }
+
+    fn open(&mut self) -> Option<CircuitState> {
+        self.opened_at = Instant::now();
+        self.transition(CircuitState::Open)
+    }
+}
+
+/// Re-opens the circuit if a trial request panics, which would otherwise
+/// leave it half-open with no trial in flight, rejecting every request.
+struct TrialGuard<'a> {
+    breaker: &'a CircuitBreaker,
+    armed: bool,
+}
+
+impl Drop for TrialGuard<'_> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let changed = self
+            .breaker
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .open();
+        self.breaker.notify(changed);
+    }
+}
+
+impl fmt::Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("failure_threshold", &self.failure_threshold)
+            .field("cool_down", &self.cool_down)
+            .field("state", &self.state())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::StatusCode;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::{Arc, OnceLock, Weak};
+
+    fn unavailable() -> Result<(), Error> {
+        Err(Error::Status {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            retry_after: None,
+        })
+    }
+
+    #[test]
+    fn opens_after_threshold_and_closes_after_trial() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(3600));
+        assert!(breaker.call(unavailable).is_err());
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.call(unavailable).is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(matches!(breaker.call(|| Ok(())), Err(Error::CircuitOpen)));
+
+        let breaker = CircuitBreaker::new(1, Duration::ZERO);
+        assert!(breaker.call(unavailable).is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(breaker.call(|| Ok(())).is_ok());
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn listeners_can_inspect_the_breaker() {
+        let this: Arc<OnceLock<Weak<CircuitBreaker>>> = Arc::default();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let breaker = Arc::new(CircuitBreaker::new(1, Duration::ZERO).on_state_change({
+            let (this, seen) = (this.clone(), seen.clone());
+            move |state| {
+                let breaker = this.get().and_then(Weak::upgrade).unwrap();
+                assert_eq!(breaker.state(), state);
+                seen.lock().unwrap().push(state);
+            }
+        }));
+        this.set(Arc::downgrade(&breaker)).unwrap();
+
+        assert!(breaker.call(unavailable).is_err());
+        assert!(breaker.call(|| Ok(())).is_ok());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                CircuitState::Open,
+                CircuitState::HalfOpen,
+                CircuitState::Closed
+            ]
+        );
+    }
+
+    #[test]
+    fn panicking_trial_reopens_the_circuit() {
+        let breaker = CircuitBreaker::new(1, Duration::ZERO);
+        assert!(breaker.call(unavailable).is_err());
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            breaker.call::<()>(|| panic!("trial panicked"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(breaker.call(|| Ok(())).is_ok());
+    }
+}
@@ -0,0 +1,650 @@
+use crate::cache::{
+    CachedTokens, MemoryTokenCache, TokenCache, DEFAULT_FAILURE_INTERVAL, DEFAULT_REFRESH_MARGIN,
+};
+use crate::proxy;
+use crate::revoke;
+use crate::token_info;
+use crate::{
+    collect_scopes, create_jwt, default_headers, default_http_client, http_client_builder,
+    user_agent, AccessToken, AuditEntry, AuditLog, AuditOutcome, CircuitBreaker, Claims, Clock,
+    Credentials, Error, HttpResponse, HttpTransport, IdTokenResponse, RetryPolicy, SystemClock,
+    TokenObserver, TokenProvider, TokenResponse, DEFAULT_CLOCK_SKEW, DEFAULT_TIMEOUT, GRANT_TYPE,
+    MAX_ASSERTION_LIFETIME,
+};
+#[cfg(feature = "default-tls")]
+use crate::{ClientCertificate, MTLS_TOKEN_URL, TOKEN_URL};
+use reqwest::header::{HeaderMap, USER_AGENT};
+use reqwest::{Client as HTTPClient, Proxy};
+use serde_json::{Map, Value};
+use std::cell::Cell;
+use std::cmp;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<ClientInner>,
+}
+
+struct ClientInner {
+    credentials: Credentials,
+    scopes: String,
+    token_url: String,
+    headers: HeaderMap,
+    subject: Option<String>,
+    self_signed_audience: Option<String>,
+    audience: Option<String>,
+    assertion_lifetime: Duration,
+    clock_skew: Duration,
+    clock: Arc<dyn Clock>,
+    claims: Map<String, Value>,
+    retry: RetryPolicy,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    observer: Option<Arc<dyn TokenObserver>>,
+    audit_log: Option<Arc<dyn AuditLog>>,
+    http: HTTPClient,
+    transport: Arc<dyn HttpTransport>,
+    access_tokens: CachedTokens,
+    id_tokens: CachedTokens,
+}
+
+impl Client {
+    pub fn new(
+        credentials: Credentials,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Client {
+        Self::with_http_client(credentials, scopes, default_http_client())
+    }
+
+    pub fn with_http_client(
+        credentials: Credentials,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+        http: HTTPClient,
+    ) -> Client {
+        let inner = ClientInner {
+            token_url: credentials.token_uri().to_owned(),
+            credentials,
+            scopes: normalize_scopes(scopes),
+            headers: default_headers(),
+            subject: None,
+            self_signed_audience: None,
+            audience: None,
+            assertion_lifetime: MAX_ASSERTION_LIFETIME,
+            clock_skew: DEFAULT_CLOCK_SKEW,
+            clock: Arc::new(SystemClock),
+            claims: Map::new(),
+            retry: RetryPolicy::default(),
+            circuit_breaker: None,
+            observer: None,
+            audit_log: None,
+            transport: Arc::new(http.clone()),
+            http,
+            access_tokens: CachedTokens::default(),
+            id_tokens: CachedTokens::default(),
+        };
+        Client {
+            inner: Arc::new(inner),
+        }
+    }
+
+    pub fn builder(
+        credentials: Credentials,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> ClientBuilder {
+        ClientBuilder::new(credentials, scopes)
+    }
+
+    pub fn get_token(&self) -> Result<AccessToken, Error> {
+        let inner = &self.inner;
+        inner
+            .access_tokens
+            .get_or_fetch(&inner.scopes, || inner.new_token(&inner.scopes))
+    }
+
+    pub fn get_token_with_scopes(
+        &self,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<AccessToken, Error> {
+        let scopes = normalize_scopes(scopes);
+        self.inner
+            .access_tokens
+            .get_or_fetch(&scopes, || self.inner.new_token(&scopes))
+    }
+
+    pub fn force_refresh(&self) -> Result<AccessToken, Error> {
+        let inner = &self.inner;
+        inner
+            .access_tokens
+            .refresh(&inner.scopes, || inner.new_token(&inner.scopes))
+    }
+
+    pub fn invalidate(&self) {
+        self.inner.access_tokens.invalidate_key(&self.inner.scopes);
+        self.inner.access_tokens.invalidate();
+        self.inner.id_tokens.invalidate();
+    }
+
+    /// Revokes `token` and clears the cached tokens so it is not handed out
+    /// again.
+    pub fn revoke_token(&self, token: &AccessToken) -> Result<(), Error> {
+        revoke::revoke(&self.inner.http, &token.value)?;
+        self.invalidate();
+        Ok(())
+    }
+
+    pub fn get_id_token(&self, audience: &str) -> Result<AccessToken, Error> {
+        self.inner
+            .id_tokens
+            .get_or_fetch(audience, || self.inner.fetch_id_token(audience))
+    }
+
+    /// Fetches a fresh token and checks it against Google's tokeninfo endpoint,
+    /// so misconfigured credentials fail at startup rather than on first use.
+    pub fn verify(&self) -> Result<(), Error> {
+        let inner = &self.inner;
+        inner.credentials.validate()?;
+        if inner.self_signed_audience.is_some() {
+            return Ok(());
+        }
+
+        let token = self.force_refresh()?;
+        let info = inner
+            .retry
+            .retry(|| token_info::fetch(&inner.http, inner.headers.clone(), &token.value))?;
+        let expected = inner
+            .subject
+            .as_ref()
+            .unwrap_or(&inner.credentials.client_email);
+        match info.email {
+            Some(email) if &email != expected => Err(Error::InvalidCredentials(format!(
+                "token was issued to {} rather than {}",
+                email, expected
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ClientInner {
+    fn new_token(&self, scopes: &str) -> Result<AccessToken, Error> {
+        match &self.self_signed_audience {
+            Some(audience) => self.create_self_signed_token(audience),
+            None => self.fetch_token(scopes),
+        }
+    }
+
+    fn claims(&self) -> Result<Claims, Error> {
+        let audience = self.audience.as_ref().unwrap_or(&self.token_url);
+        Ok(Claims {
+            extra: self.claims.clone(),
+            ..Claims::with_lifetime(
+                &self.credentials,
+                audience,
+                self.assertion_lifetime,
+                self.clock.now() - self.clock_skew,
+            )?
+        })
+    }
+
+    fn fetch_token(&self, scopes: &str) -> Result<AccessToken, Error> {
+        let claims = Claims {
+            scope: Some(scopes.to_owned()),
+            sub: self.subject.clone(),
+            ..self.claims()?
+        };
+        let token = create_jwt(&self.credentials, &claims)?;
+        let params = [("grant_type", GRANT_TYPE), ("assertion", &token)];
+
+        self.send(Some(scopes), None, |remote_addr| {
+            self.post(&params, remote_addr)?
+                .json::<TokenResponse>()
+                .map(|response| AccessToken::from_response(response, self.clock.now()))
+        })
+    }
+
+    fn create_self_signed_token(&self, audience: &str) -> Result<AccessToken, Error> {
+        let claims = Claims {
+            sub: Some(self.credentials.client_email.clone()),
+            aud: audience.to_owned(),
+            ..self.claims()?
+        };
+        let expires = UNIX_EPOCH + Duration::from_secs(claims.exp);
+        Ok(AccessToken::new(
+            create_jwt(&self.credentials, &claims)?,
+            expires,
+        ))
+    }
+
+    fn fetch_id_token(&self, audience: &str) -> Result<AccessToken, Error> {
+        let claims = Claims {
+            target_audience: Some(audience.to_owned()),
+            ..self.claims()?
+        };
+        let token = create_jwt(&self.credentials, &claims)?;
+        let params = [("grant_type", GRANT_TYPE), ("assertion", &token)];
+
+        let response = self.send(None, Some(audience), |remote_addr| {
+            self.post(&params, remote_addr)?.json::<IdTokenResponse>()
+        })?;
+        AccessToken::from_id_token(response.id_token)
+    }
+
+    fn send<T>(
+        &self,
+        scopes: Option<&str>,
+        audience: Option<&str>,
+        mut request: impl FnMut(&Cell<Option<SocketAddr>>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let timestamp = SystemTime::now();
+        let started = Instant::now();
+        let remote_addr = Cell::new(None);
+        let request = || request(&remote_addr);
+        let result = match &self.circuit_breaker {
+            Some(breaker) => breaker.call(|| self.retry.retry(request)),
+            None => self.retry.retry(request),
+        };
+        let elapsed = started.elapsed();
+        match &result {
+            Ok(_) => log_debug!("token request to {} took {:?}", self.token_url, elapsed),
+            Err(err) => log_warn!("token request to {} failed: {}", self.token_url, err),
+        }
+        if let Some(observer) = &self.observer {
+            match &result {
+                Ok(_) => observer.fetched(elapsed),
+                Err(err) => observer.failed(err, elapsed),
+            }
+        }
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(&AuditEntry {
+                timestamp,
+                client_email: self.credentials.client_email.clone(),
+                subject: self.subject.clone(),
+                scopes: scopes.map(str::to_owned),
+                audience: audience.map(str::to_owned),
+                token_url: self.token_url.clone(),
+                remote_addr: remote_addr.get(),
+                outcome: AuditOutcome::new(&result),
+                latency: elapsed,
+            });
+        }
+        result
+    }
+
+    fn post(
+        &self,
+        params: &[(&str, &str)],
+        remote_addr: &Cell<Option<SocketAddr>>,
+    ) -> Result<HttpResponse, Error> {
+        let response = self
+            .transport
+            .post_form(&self.token_url, &self.headers, params)?;
+        remote_addr.set(response.remote_addr);
+        Ok(response)
+    }
+}
+
+impl TokenProvider for Client {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        Client::get_token(self)
+    }
+
+    fn invalidate(&self) {
+        Client::invalidate(self)
+    }
+
+    fn force_refresh(&self) -> Result<AccessToken, Error> {
+        Client::force_refresh(self)
+    }
+
+    fn quota_project_id(&self) -> Option<&str> {
+        self.inner.credentials.quota_project_id()
+    }
+
+    fn universe_domain(&self) -> &str {
+        self.inner.credentials.universe_domain()
+    }
+}
+
+pub struct ClientBuilder {
+    credentials: Credentials,
+    scopes: String,
+    token_url: Option<String>,
+    http: Option<HTTPClient>,
+    transport: Option<Arc<dyn HttpTransport>>,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    proxy: Option<String>,
+    proxy_auth: Option<(String, String)>,
+    #[cfg(feature = "default-tls")]
+    client_certificate: Option<ClientCertificate>,
+    user_agent: Option<String>,
+    subject: Option<String>,
+    self_signed_audience: Option<String>,
+    audience: Option<String>,
+    assertion_lifetime: Duration,
+    clock_skew: Duration,
+    clock: Arc<dyn Clock>,
+    claims: Map<String, Value>,
+    token_cache: Option<Arc<dyn TokenCache>>,
+    serve_stale: bool,
+    failure_interval: Duration,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    observer: Option<Arc<dyn TokenObserver>>,
+    audit_log: Option<Arc<dyn AuditLog>>,
+    refresh_margin: Option<Duration>,
+    retry: RetryPolicy,
+    warm_up: bool,
+}
+
+impl ClientBuilder {
+    pub fn new(
+        credentials: Credentials,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Self {
+        ClientBuilder {
+            credentials,
+            scopes: normalize_scopes(scopes),
+            token_url: None,
+            http: None,
+            transport: None,
+            connect_timeout: None,
+            timeout: None,
+            proxy: None,
+            proxy_auth: None,
+            #[cfg(feature = "default-tls")]
+            client_certificate: None,
+            user_agent: None,
+            subject: None,
+            self_signed_audience: None,
+            audience: None,
+            assertion_lifetime: MAX_ASSERTION_LIFETIME,
+            clock_skew: DEFAULT_CLOCK_SKEW,
+            clock: Arc::new(SystemClock),
+            claims: Map::new(),
+            token_cache: None,
+            serve_stale: false,
+            failure_interval: DEFAULT_FAILURE_INTERVAL,
+            circuit_breaker: None,
+            observer: None,
+            audit_log: None,
+            refresh_margin: None,
+            retry: RetryPolicy::default(),
+            warm_up: false,
+        }
+    }
+
+    pub fn token_url(mut self, token_url: impl Into<String>) -> Self {
+        self.token_url = Some(token_url.into());
+        self
+    }
+
+    /// Use a pre-configured HTTP client. The timeout and proxy options are ignored in this case.
+    pub fn http_client(mut self, http: HTTPClient) -> Self {
+        self.http = Some(http);
+        self
+    }
+
+    /// Sends token endpoint requests through `transport` instead of the HTTP
+    /// client. Revocation and token info requests still use the HTTP client.
+    pub fn transport(mut self, transport: Arc<dyn HttpTransport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Defaults to 10 seconds.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Total time allowed for each token request. Defaults to 10 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Route token requests through this proxy instead of the one configured by
+    /// the `HTTPS_PROXY`, `HTTP_PROXY` and `NO_PROXY` environment variables.
+    pub fn proxy(mut self, url: impl Into<String>) -> Self {
+        self.proxy = Some(url.into());
+        self
+    }
+
+    /// Presents `certificate` to Google, fetching tokens from the mTLS token
+    /// endpoint unless the credentials or `token_url` name another one.
+    #[cfg(feature = "default-tls")]
+    pub fn client_certificate(mut self, certificate: ClientCertificate) -> Self {
+        self.client_certificate = Some(certificate);
+        self
+    }
+
+    pub fn proxy_auth(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.proxy_auth = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Appends a product token to the User-Agent sent with token requests.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    pub fn subject(mut self, subject: impl Into<String>) -> Self {
+        self.subject = Some(subject.into());
+        self
+    }
+
+    pub fn self_signed(mut self, audience: impl Into<String>) -> Self {
+        self.self_signed_audience = Some(audience.into());
+        self
+    }
+
+    /// Overrides the `aud` claim, which defaults to the token URL.
+    pub fn audience(mut self, audience: impl Into<String>) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+
+    /// How long signed assertions are valid for, capped at one hour.
+    pub fn assertion_lifetime(mut self, lifetime: Duration) -> Self {
+        self.assertion_lifetime = cmp::min(lifetime, MAX_ASSERTION_LIFETIME);
+        self
+    }
+
+    /// How far to backdate `iat` to tolerate a fast local clock. Defaults to 10 seconds.
+    pub fn clock_skew(mut self, skew: Duration) -> Self {
+        self.clock_skew = skew;
+        self
+    }
+
+    /// Replaces the system clock, mainly so tests can control token expiry.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Adds a private claim to every signed assertion.
+    pub fn claim(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.claims.insert(name.into(), value.into());
+        self
+    }
+
+    /// Stores tokens in `cache` instead of a private in-memory map.
+    pub fn token_cache(mut self, cache: Arc<dyn TokenCache>) -> Self {
+        self.token_cache = Some(cache);
+        self
+    }
+
+    /// If refreshing fails while the cached token is still valid, return the
+    /// cached token instead of the error. Refreshes are retried at most every
+    /// five seconds until one succeeds or the token expires.
+    pub fn serve_stale(mut self, serve_stale: bool) -> Self {
+        self.serve_stale = serve_stale;
+        self
+    }
+
+    /// After a failed refresh, fail fast with `Error::Throttled` for at least
+    /// `interval` (or as long as the endpoint's `Retry-After` asks) instead of
+    /// sending another request. Defaults to one second; `force_refresh` is
+    /// not throttled.
+    pub fn failure_interval(mut self, interval: Duration) -> Self {
+        self.failure_interval = interval;
+        self
+    }
+
+    /// Guards token requests with a circuit breaker, which may be shared
+    /// between clients that use the same token endpoint.
+    pub fn circuit_breaker(mut self, breaker: Arc<CircuitBreaker>) -> Self {
+        self.circuit_breaker = Some(breaker);
+        self
+    }
+
+    pub fn observer(mut self, observer: Arc<dyn TokenObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Records every token request, successful or not, in `audit_log`.
+    pub fn audit_log(mut self, audit_log: Arc<dyn AuditLog>) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    pub fn refresh_margin(mut self, margin: Duration) -> Self {
+        self.refresh_margin = Some(margin);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Fetches the first token in `build`, so bad credentials fail there and
+    /// the first request does not wait for the token endpoint.
+    pub fn warm_up(mut self, warm_up: bool) -> Self {
+        self.warm_up = warm_up;
+        self
+    }
+
+    pub fn build(self) -> Result<Client, Error> {
+        let http = match self.http {
+            Some(http) => http,
+            None => {
+                let proxy = match &self.proxy {
+                    Some(url) => Some(Proxy::all(url.as_str())?),
+                    None => proxy::from_env(),
+                };
+                let proxy = match (proxy, &self.proxy_auth) {
+                    (Some(proxy), Some((username, password))) => {
+                        Some(proxy.basic_auth(username, password))
+                    }
+                    (proxy, _) => proxy,
+                };
+                #[allow(unused_mut)]
+                let mut builder = http_client_builder(
+                    self.connect_timeout.unwrap_or(DEFAULT_TIMEOUT),
+                    self.timeout.unwrap_or(DEFAULT_TIMEOUT),
+                    proxy,
+                );
+                #[cfg(feature = "default-tls")]
+                {
+                    if let Some(certificate) = &self.client_certificate {
+                        builder = builder.identity(certificate.identity()?);
+                    }
+                }
+                builder.build()?
+            }
+        };
+        let margin = self.refresh_margin.unwrap_or(DEFAULT_REFRESH_MARGIN);
+        let store = self
+            .token_cache
+            .unwrap_or_else(|| Arc::new(MemoryTokenCache::new()));
+        let token_url = match self.token_url {
+            Some(token_url) => token_url,
+            #[cfg(feature = "default-tls")]
+            None if self.client_certificate.is_some()
+                && self.credentials.token_uri() == TOKEN_URL =>
+            {
+                MTLS_TOKEN_URL.to_owned()
+            }
+            None => self.credentials.token_uri().to_owned(),
+        };
+
+        // Shared stores, such as a `FileTokenCache`, must not hand out tokens
+        // minted by another key, or by another token endpoint.
+        let credentials = &self.credentials;
+        let email = &credentials.client_email;
+        let key = credentials
+            .private_key_id
+            .clone()
+            .or_else(|| credentials.private_key.fingerprint())
+            .unwrap_or_default();
+        let kind = match &self.self_signed_audience {
+            Some(audience) => format!("jwt:{}", audience),
+            None => "access".to_owned(),
+        };
+        let subject = self.subject.as_deref().unwrap_or_default();
+        let access_tokens = CachedTokens::with_store(
+            store.clone(),
+            format!("{}:{}:{}:{}:{}:", kind, email, key, subject, token_url),
+            margin,
+            self.clock.clone(),
+        )
+        .serve_stale(self.serve_stale)
+        .failure_interval(self.failure_interval)
+        .observer(self.observer.clone());
+        let id_tokens = CachedTokens::with_store(
+            store,
+            format!("id:{}:{}:{}:", email, key, token_url),
+            margin,
+            self.clock.clone(),
+        )
+        .serve_stale(self.serve_stale)
+        .failure_interval(self.failure_interval)
+        .observer(self.observer.clone());
+
+        let mut headers = default_headers();
+        if let Some(product) = &self.user_agent {
+            headers.insert(USER_AGENT, user_agent(product)?);
+        }
+
+        let warm_up = self.warm_up;
+        let inner = ClientInner {
+            token_url,
+            credentials: self.credentials,
+            scopes: self.scopes,
+            headers,
+            subject: self.subject,
+            self_signed_audience: self.self_signed_audience,
+            audience: self.audience,
+            assertion_lifetime: self.assertion_lifetime,
+            clock_skew: self.clock_skew,
+            clock: self.clock,
+            claims: self.claims,
+            retry: self.retry,
+            circuit_breaker: self.circuit_breaker,
+            observer: self.observer,
+            audit_log: self.audit_log,
+            transport: self.transport.unwrap_or_else(|| Arc::new(http.clone())),
+            http,
+            access_tokens,
+            id_tokens,
+        };
+        let client = Client {
+            inner: Arc::new(inner),
+        };
+        if warm_up {
+            client.get_token()?;
+        }
+        Ok(client)
+    }
+}
+
+fn normalize_scopes(scopes: impl IntoIterator<Item = impl AsRef<str>>) -> String {
+    let mut scopes = collect_scopes(scopes);
+    scopes.sort();
+    scopes.dedup();
+    scopes.join(" ")
+}
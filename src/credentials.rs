@@ -0,0 +1,352 @@
+use crate::key::PrivateKey;
+use crate::secret::{self, SecretBytes};
+use crate::{create_jwt, Claims, Error, Signer, DEFAULT_UNIVERSE_DOMAIN, TOKEN_URL};
+#[cfg(feature = "blocking")]
+use crate::{kms, IamSigner, KmsKey, TokenProvider};
+#[cfg(feature = "openssl")]
+use openssl::{pkcs12::Pkcs12, pkey::Private, rsa::Rsa};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const CREDENTIALS_ENV_VAR: &str = "GOOGLE_APPLICATION_CREDENTIALS";
+const WELL_KNOWN_FILE: &str = "application_default_credentials.json";
+const GCLOUD_CONFIG_ENV_VAR: &str = "CLOUDSDK_CONFIG";
+
+#[derive(Clone)]
+pub struct Credentials {
+    pub(crate) private_key: PrivateKey,
+    pub(crate) client_email: String,
+    pub(crate) private_key_id: Option<String>,
+    token_uri: Option<String>,
+    project_id: Option<String>,
+    quota_project_id: Option<String>,
+    universe_domain: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CredentialsFile {
+    #[serde(rename = "type")]
+    credentials_type: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ServiceAccountKey {
+    private_key: String,
+    private_key_id: Option<String>,
+    client_email: String,
+    token_uri: Option<String>,
+    project_id: Option<String>,
+    quota_project_id: Option<String>,
+    universe_domain: Option<String>,
+}
+
+impl Credentials {
+    #[cfg(feature = "openssl")]
+    pub fn new(private_key: Rsa<Private>, client_email: String) -> Self {
+        Self::with_key(PrivateKey::Openssl(private_key), client_email)
+    }
+
+    pub fn from_pem(pem: &str, client_email: impl Into<String>) -> Result<Self, Error> {
+        let private_key = PrivateKey::from_pem(pem)?;
+        Ok(Self::with_key(private_key, client_email.into()))
+    }
+
+    pub fn from_der(der: &[u8], client_email: impl Into<String>) -> Result<Self, Error> {
+        let private_key = PrivateKey::from_der(SecretBytes::new(der.to_vec()))?;
+        Ok(Self::with_key(private_key, client_email.into()))
+    }
+
+    #[cfg(feature = "openssl")]
+    pub fn from_p12(
+        path: impl AsRef<Path>,
+        passphrase: &str,
+        client_email: impl Into<String>,
+    ) -> Result<Self, Error> {
+        let der = SecretBytes::new(fs::read(path)?);
+        let private_key = Pkcs12::from_der(&der)?
+            .parse2(passphrase)?
+            .pkey
+            .ok_or_else(|| Error::InvalidKey("p12 file has no private key".to_owned()))?
+            .rsa()?;
+        Ok(Self::with_key(
+            PrivateKey::Openssl(private_key),
+            client_email.into(),
+        ))
+    }
+
+    /// Signs assertions with a Cloud KMS key instead of a local private key.
+    /// The key's public half must be uploaded to the service account.
+    #[cfg(feature = "blocking")]
+    pub fn from_kms(key: KmsKey, client_email: impl Into<String>) -> Self {
+        Self::from_signer(Arc::new(key), client_email)
+    }
+
+    /// Signs assertions with an external signer instead of a local private
+    /// key.
+    pub fn from_signer(signer: Arc<dyn Signer>, client_email: impl Into<String>) -> Self {
+        Self::with_key(PrivateKey::Signer(signer), client_email.into())
+    }
+
+    /// Signs assertions through the IAM Credentials API as `signer`'s service
+    /// account, without any local key.
+    #[cfg(feature = "blocking")]
+    pub fn from_iam(signer: IamSigner) -> Self {
+        let client_email = signer.email().to_owned();
+        Self::with_key(PrivateKey::Iam(Arc::new(signer)), client_email)
+    }
+
+    fn with_key(private_key: PrivateKey, client_email: String) -> Self {
+        Credentials {
+            private_key,
+            client_email,
+            private_key_id: None,
+            token_uri: None,
+            project_id: None,
+            quota_project_id: None,
+            universe_domain: None,
+        }
+    }
+
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let json = fs::read_to_string(path)?;
+        Self::from_json_str(&json)
+    }
+
+    /// Loads a JSON key written by [`encrypt_key_file`](crate::encrypt_key_file),
+    /// decrypting it in memory only.
+    #[cfg(feature = "ring")]
+    pub fn from_encrypted_file(path: impl AsRef<Path>, passphrase: &str) -> Result<Self, Error> {
+        let json = crate::encrypted::decrypt_key_file(&fs::read(path)?, passphrase)?;
+        Self::from_json_bytes(&json)
+    }
+
+    /// Loads a JSON key encrypted with a Cloud KMS symmetric key, e.g. by
+    /// `gcloud kms encrypt`, decrypting it in memory only. `provider`
+    /// authorizes the call to Cloud KMS.
+    #[cfg(feature = "blocking")]
+    pub fn from_kms_encrypted_file(
+        path: impl AsRef<Path>,
+        crypto_key: &str,
+        provider: &dyn TokenProvider,
+    ) -> Result<Self, Error> {
+        let json = kms::decrypt(crypto_key, provider, &fs::read(path)?)?;
+        Self::from_json_bytes(&json)
+    }
+
+    #[cfg(feature = "ring")]
+    fn from_json_bytes(json: &[u8]) -> Result<Self, Error> {
+        let json = std::str::from_utf8(json)
+            .map_err(|_| Error::InvalidCredentials("key file is not UTF-8".to_owned()))?;
+        Self::from_json_str(json)
+    }
+
+    pub fn from_json_str(json: &str) -> Result<Self, Error> {
+        let mut key: ServiceAccountKey = serde_json::from_str(json)?;
+        let private_key = PrivateKey::from_pem(&key.private_key);
+        secret::wipe_str(&mut key.private_key);
+        // Other universes have no default endpoint to fall back to.
+        let universe_domain = &key.universe_domain;
+        let token_uri = key.token_uri.or_else(|| {
+            universe_domain
+                .as_ref()
+                .filter(|domain| *domain != DEFAULT_UNIVERSE_DOMAIN)
+                .map(|domain| format!("https://oauth2.{}/token", domain))
+        });
+        Ok(Credentials {
+            private_key: private_key?,
+            client_email: key.client_email,
+            private_key_id: key.private_key_id,
+            token_uri,
+            project_id: key.project_id,
+            quota_project_id: key.quota_project_id,
+            universe_domain: key.universe_domain,
+        })
+    }
+
+    /// Loads a service account key from an environment variable holding the
+    /// JSON itself, either verbatim or base64 encoded.
+    pub fn from_env_json(var: &str) -> Result<Self, Error> {
+        let mut json = env_json(var)?;
+        let credentials = Self::from_json_str(&json);
+        secret::wipe_str(&mut json);
+        credentials
+    }
+
+    pub fn application_default() -> Result<Self, Error> {
+        match application_default_file() {
+            Some(path) => Self::from_json_file(path),
+            None => Err(Error::CredentialsNotFound),
+        }
+    }
+
+    /// Checks that the key can sign an assertion. External and IAM
+    /// signers may contact Google to do so.
+    pub fn validate(&self) -> Result<(), Error> {
+        if !self.client_email.contains('@') {
+            return Err(Error::InvalidCredentials(format!(
+                "client_email {:?} is not an email address",
+                self.client_email
+            )));
+        }
+        create_jwt(self, &Claims::new(self, self.token_uri())?)?;
+        Ok(())
+    }
+
+    pub fn client_email(&self) -> &str {
+        &self.client_email
+    }
+
+    pub fn private_key_id(&self) -> Option<&str> {
+        self.private_key_id.as_deref()
+    }
+
+    pub fn project_id(&self) -> Option<&str> {
+        self.project_id.as_deref()
+    }
+
+    pub fn quota_project_id(&self) -> Option<&str> {
+        self.quota_project_id.as_deref()
+    }
+
+    /// The token endpoint from the key file, or Google's default endpoint.
+    pub fn token_uri(&self) -> &str {
+        self.token_uri.as_deref().unwrap_or(TOKEN_URL)
+    }
+
+    pub fn universe_domain(&self) -> &str {
+        self.universe_domain
+            .as_deref()
+            .unwrap_or(DEFAULT_UNIVERSE_DOMAIN)
+    }
+
+    /// The JWS name of the algorithm `sign_bytes` signs with, e.g. `RS256`.
+    pub fn signing_algorithm(&self) -> &str {
+        self.private_key.algorithm()
+    }
+
+    /// Signs `message` with the service account's key, through IAM
+    /// signBlob for IAM backed credentials.
+    pub fn sign_bytes(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        self.private_key.sign_bytes(message)
+    }
+
+    /// Signs `claims` as a JWT with the service account's key, through IAM
+    /// signJwt for IAM backed credentials.
+    pub fn sign_jwt(&self, claims: &impl Serialize) -> Result<String, Error> {
+        self.private_key
+            .sign_jwt(self.private_key_id.as_deref(), claims)
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("private_key", &"<redacted>")
+            .field("client_email", &self.client_email)
+            .field("private_key_id", &self.private_key_id)
+            .field("token_uri", &self.token_uri)
+            .field("project_id", &self.project_id)
+            .field("quota_project_id", &self.quota_project_id)
+            .field("universe_domain", &self.universe_domain)
+            .finish()
+    }
+}
+
+pub(crate) fn credentials_type(json: &str) -> Result<String, Error> {
+    let file: CredentialsFile = serde_json::from_str(json)?;
+    Ok(file
+        .credentials_type
+        .unwrap_or_else(|| "service_account".to_owned()))
+}
+
+fn env_json(var: &str) -> Result<String, Error> {
+    let mut value = match env::var(var) {
+        Ok(value) => value,
+        Err(env::VarError::NotPresent) => return Err(Error::CredentialsNotFound),
+        Err(env::VarError::NotUnicode(_)) => {
+            return Err(Error::InvalidCredentials(format!(
+                "{} is not valid UTF-8",
+                var
+            )))
+        }
+    };
+    if value.trim_start().starts_with('{') {
+        return Ok(value);
+    }
+
+    let encoded: String = value.split_whitespace().collect();
+    secret::wipe_str(&mut value);
+    let decoded = base64::decode(&encoded)
+        .or_else(|_| base64::decode_config(&encoded, base64::URL_SAFE))
+        .map(SecretBytes::new);
+    drop(SecretBytes::new(encoded.into_bytes()));
+    let decoded = decoded.map_err(|_| {
+        Error::InvalidCredentials(format!("{} is neither JSON nor base64 encoded JSON", var))
+    })?;
+    String::from_utf8(decoded.to_vec())
+        .map_err(|_| Error::InvalidCredentials(format!("{} does not decode to UTF-8", var)))
+}
+
+pub(crate) fn application_default_file() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(CREDENTIALS_ENV_VAR) {
+        return Some(PathBuf::from(path));
+    }
+
+    well_known_file().filter(|path| path.is_file())
+}
+
+fn well_known_file() -> Option<PathBuf> {
+    Some(gcloud_config_dir()?.join(WELL_KNOWN_FILE))
+}
+
+fn gcloud_config_dir() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(GCLOUD_CONFIG_ENV_VAR) {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = if cfg!(windows) {
+        PathBuf::from(env::var_os("APPDATA")?)
+    } else {
+        PathBuf::from(env::var_os("HOME")?).join(".config")
+    };
+    Some(config_dir.join("gcloud"))
+}
+
+/// The credentials file gcloud keeps for the account of its active
+/// configuration, as written by `gcloud auth login`.
+pub(crate) fn gcloud_credentials_file() -> Option<PathBuf> {
+    let config_dir = gcloud_config_dir()?;
+    let active = fs::read_to_string(config_dir.join("active_config"))
+        .map(|name| name.trim().to_owned())
+        .unwrap_or_else(|_| "default".to_owned());
+    let config = fs::read_to_string(
+        config_dir
+            .join("configurations")
+            .join(format!("config_{}", active)),
+    )
+    .ok()?;
+    let account = gcloud_account(&config)?;
+    let path = config_dir
+        .join("legacy_credentials")
+        .join(account)
+        .join("adc.json");
+    Some(path).filter(|path| path.is_file())
+}
+
+fn gcloud_account(config: &str) -> Option<&str> {
+    let mut in_core = false;
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_core = line == "[core]";
+        } else if in_core {
+            let mut parts = line.splitn(2, '=');
+            if parts.next().map(str::trim) == Some("account") {
+                return parts.next().map(str::trim).filter(|a| !a.is_empty());
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,160 @@
+use crate::token_storage::{load_or_authorize, StorageSlot};
+use crate::user::USER_TOKEN_URL;
+use crate::{
+    collect_scopes, default_headers, default_http_client, parse_response, Error, TokenStorage,
+    UserCredentials,
+};
+use reqwest::Client as HTTPClient;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEVICE_CODE_URL: &str = "https://oauth2.googleapis.com/device/code";
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+const DEFAULT_INTERVAL: u64 = 5;
+
+#[derive(Deserialize, Debug)]
+struct DeviceCodeResponse {
+    device_code: String,
+    user_code: String,
+    verification_url: String,
+    expires_in: u64,
+    interval: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DeviceTokenResponse {
+    refresh_token: String,
+}
+
+/// What the user needs to complete a device flow on another device.
+#[derive(Debug, Clone)]
+pub struct DeviceCode {
+    pub user_code: String,
+    pub verification_url: String,
+    pub expires_in: Duration,
+}
+
+/// The OAuth 2.0 device authorization flow, for machines without a browser:
+/// the user enters a code at a URL on another device.
+pub struct DeviceFlow {
+    client_id: String,
+    client_secret: String,
+    scopes: Vec<String>,
+    device_code_url: String,
+    token_url: String,
+    http: HTTPClient,
+    storage: StorageSlot,
+}
+
+impl DeviceFlow {
+    pub fn new(
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> DeviceFlow {
+        DeviceFlow {
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            scopes: collect_scopes(scopes),
+            device_code_url: DEVICE_CODE_URL.to_owned(),
+            token_url: USER_TOKEN_URL.to_owned(),
+            http: default_http_client(),
+            storage: None,
+        }
+    }
+
+    pub fn device_code_url(mut self, device_code_url: impl Into<String>) -> Self {
+        self.device_code_url = device_code_url.into();
+        self
+    }
+
+    pub fn token_url(mut self, token_url: impl Into<String>) -> Self {
+        self.token_url = token_url.into();
+        self
+    }
+
+    pub fn http_client(mut self, http: HTTPClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Keeps the credentials in `storage` under `key`, so that `authorize`
+    /// only runs the flow when nothing is stored yet. Delete the entry to
+    /// authorize again, e.g. after the grant is revoked.
+    pub fn storage(mut self, storage: Arc<dyn TokenStorage>, key: impl Into<String>) -> Self {
+        self.storage = Some((storage, key.into()));
+        self
+    }
+
+    /// Runs the flow, passing the code to `present` for display. Blocks while
+    /// polling until the user approves, denies or the code expires.
+    pub fn authorize(
+        &self,
+        present: impl FnOnce(&DeviceCode) -> Result<(), Error>,
+    ) -> Result<UserCredentials, Error> {
+        load_or_authorize(&self.storage, || self.run(present))
+    }
+
+    fn run(
+        &self,
+        present: impl FnOnce(&DeviceCode) -> Result<(), Error>,
+    ) -> Result<UserCredentials, Error> {
+        let scopes = self.scopes.join(" ");
+        let mut params = HashMap::new();
+        params.insert("client_id", self.client_id.as_str());
+        params.insert("scope", &scopes);
+        let response = self
+            .http
+            .post(&self.device_code_url)
+            .headers(default_headers())
+            .form(&params)
+            .send()?;
+        let device = parse_response::<DeviceCodeResponse>(response)?;
+
+        let expires_in = Duration::from_secs(device.expires_in);
+        present(&DeviceCode {
+            user_code: device.user_code,
+            verification_url: device.verification_url,
+            expires_in,
+        })?;
+
+        let deadline = Instant::now() + expires_in;
+        let mut interval = Duration::from_secs(device.interval.unwrap_or(DEFAULT_INTERVAL));
+        loop {
+            thread::sleep(interval);
+            match self.poll(&device.device_code) {
+                Err(Error::OAuth { ref error, .. })
+                    if error == "authorization_pending" && Instant::now() < deadline => {}
+                Err(Error::OAuth { ref error, .. })
+                    if error == "slow_down" && Instant::now() < deadline =>
+                {
+                    interval += Duration::from_secs(DEFAULT_INTERVAL);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn poll(&self, device_code: &str) -> Result<UserCredentials, Error> {
+        let mut params = HashMap::new();
+        params.insert("grant_type", DEVICE_CODE_GRANT_TYPE);
+        params.insert("device_code", device_code);
+        params.insert("client_id", &self.client_id);
+        params.insert("client_secret", &self.client_secret);
+        let response = self
+            .http
+            .post(&self.token_url)
+            .headers(default_headers())
+            .form(&params)
+            .send()?;
+        let response = parse_response::<DeviceTokenResponse>(response)?;
+        Ok(UserCredentials::new(
+            self.client_id.clone(),
+            self.client_secret.clone(),
+            response.refresh_token,
+        ))
+    }
+}
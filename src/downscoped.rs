@@ -0,0 +1,157 @@
+use crate::cache::CachedToken;
+use crate::sts::{sts_token_url, ACCESS_TOKEN_TYPE};
+use crate::{AccessToken, Error, StsClient, TokenExchangeRequest, TokenProvider};
+use reqwest::Client as HTTPClient;
+use serde::Serialize;
+use std::time::Duration;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AccessBoundaryOptions<'a> {
+    access_boundary: &'a CredentialAccessBoundary,
+}
+
+/// Restricts the Cloud Storage permissions of a downscoped token.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialAccessBoundary {
+    access_boundary_rules: Vec<AccessBoundaryRule>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessBoundaryRule {
+    available_resource: String,
+    available_permissions: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    availability_condition: Option<AvailabilityCondition>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct AvailabilityCondition {
+    expression: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+}
+
+impl CredentialAccessBoundary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rule(mut self, rule: AccessBoundaryRule) -> Self {
+        self.access_boundary_rules.push(rule);
+        self
+    }
+}
+
+impl AccessBoundaryRule {
+    /// `resource` is a full resource name such as
+    /// `//storage.googleapis.com/projects/_/buckets/my-bucket`, and each
+    /// permission an IAM role prefixed with `inRole:`.
+    pub fn new(
+        resource: impl Into<String>,
+        permissions: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Self {
+        AccessBoundaryRule {
+            available_resource: resource.into(),
+            available_permissions: permissions
+                .into_iter()
+                .map(|permission| permission.as_ref().to_owned())
+                .collect(),
+            availability_condition: None,
+        }
+    }
+
+    /// Further restricts the rule with a CEL expression, e.g. to an object
+    /// name prefix.
+    pub fn condition(mut self, expression: impl Into<String>, title: Option<String>) -> Self {
+        self.availability_condition = Some(AvailabilityCondition {
+            expression: expression.into(),
+            title,
+        });
+        self
+    }
+}
+
+/// Exchanges the tokens of `source` for ones restricted by a credential
+/// access boundary, suitable for handing to less trusted components.
+pub struct DownscopedClient<P> {
+    source: P,
+    boundary: CredentialAccessBoundary,
+    sts: StsClient,
+    access_token: CachedToken,
+}
+
+impl<P: TokenProvider> DownscopedClient<P> {
+    pub fn new(source: P, boundary: CredentialAccessBoundary) -> DownscopedClient<P> {
+        let sts = StsClient::new().token_url(sts_token_url(source.universe_domain()));
+        DownscopedClient {
+            source,
+            boundary,
+            sts,
+            access_token: CachedToken::default(),
+        }
+    }
+
+    pub fn token_url(mut self, token_url: impl Into<String>) -> Self {
+        self.sts = self.sts.token_url(token_url);
+        self
+    }
+
+    pub fn http_client(mut self, http: HTTPClient) -> Self {
+        self.sts = self.sts.http_client(http);
+        self
+    }
+
+    pub fn refresh_margin(mut self, margin: Duration) -> Self {
+        self.access_token = CachedToken::new(margin);
+        self
+    }
+
+    pub fn get_token(&self) -> Result<AccessToken, Error> {
+        self.access_token.get_or_fetch(|| self.fetch_token())
+    }
+
+    pub fn force_refresh(&self) -> Result<AccessToken, Error> {
+        self.access_token.refresh(|| self.fetch_token())
+    }
+
+    pub fn invalidate(&self) {
+        self.access_token.invalidate();
+    }
+
+    fn fetch_token(&self) -> Result<AccessToken, Error> {
+        let source_token = self.source.get_token()?;
+        let options = serde_json::to_string(&AccessBoundaryOptions {
+            access_boundary: &self.boundary,
+        })?;
+        let request =
+            TokenExchangeRequest::new(&*source_token.value, ACCESS_TOKEN_TYPE).options(options);
+        let response = self.sts.exchange(&request)?;
+        // The downscoped token lives no longer than its source.
+        Ok(response.into_access_token(source_token.expires))
+    }
+}
+
+impl<P: TokenProvider> TokenProvider for DownscopedClient<P> {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        DownscopedClient::get_token(self)
+    }
+
+    fn invalidate(&self) {
+        DownscopedClient::invalidate(self)
+    }
+
+    fn force_refresh(&self) -> Result<AccessToken, Error> {
+        DownscopedClient::force_refresh(self)
+    }
+
+    fn quota_project_id(&self) -> Option<&str> {
+        self.source.quota_project_id()
+    }
+
+    fn universe_domain(&self) -> &str {
+        self.source.universe_domain()
+    }
+}
@@ -0,0 +1,47 @@
+use crate::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters.
+pub(crate) fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// The `YYYYMMDD` date and `YYYYMMDDTHHMMSSZ` timestamp used by AWS SigV4
+/// and Cloud Storage V4 signatures.
+pub(crate) fn timestamps(now: SystemTime) -> Result<(String, String), Error> {
+    let secs = now.duration_since(UNIX_EPOCH)?.as_secs();
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+
+    // Civil date from days since the epoch, per Howard Hinnant's algorithm.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let timestamp = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    );
+    Ok((date, timestamp))
+}
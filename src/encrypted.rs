@@ -0,0 +1,150 @@
+use crate::secret::SecretBytes;
+use crate::Error;
+use ring::aead::{self, OpeningKey, SealingKey, AES_256_GCM};
+use ring::digest::SHA256;
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+
+// The format is the magic, the PBKDF2 iteration count as a big-endian u32,
+// the salt, the nonce, then the AES-256-GCM sealed key file. Everything
+// before the nonce is authenticated along with the ciphertext.
+const MAGIC: &[u8] = b"GTPKEY01";
+const ITERATIONS: u32 = 600_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+const HEADER_LEN: usize = MAGIC.len() + 4 + SALT_LEN;
+
+/// Encrypts a service account JSON key with `passphrase`, in the format
+/// read by [`Credentials::from_encrypted_file`](crate::Credentials::from_encrypted_file).
+pub fn encrypt_key_file(json: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    let mut salt = [0; SALT_LEN];
+    let mut nonce = [0; NONCE_LEN];
+    let rng = SystemRandom::new();
+    rng.fill(&mut salt)
+        .and_then(|_| rng.fill(&mut nonce))
+        .map_err(|_| crypto_error("failed to generate random bytes"))?;
+
+    let mut output = Vec::with_capacity(HEADER_LEN + NONCE_LEN + json.len() + 16);
+    output.extend_from_slice(MAGIC);
+    output.extend_from_slice(&ITERATIONS.to_be_bytes());
+    output.extend_from_slice(&salt);
+    let key = derive_key(passphrase, &salt, ITERATIONS);
+    let key = SealingKey::new(&AES_256_GCM, &key)
+        .map_err(|_| crypto_error("failed to create encryption key"))?;
+
+    let mut in_out = Vec::with_capacity(json.len() + AES_256_GCM.tag_len());
+    in_out.extend_from_slice(json);
+    in_out.resize(json.len() + AES_256_GCM.tag_len(), 0);
+    let mut in_out = SecretBytes::new(in_out);
+    let len = aead::seal_in_place(
+        &key,
+        &nonce,
+        &output[..HEADER_LEN],
+        &mut in_out,
+        AES_256_GCM.tag_len(),
+    )
+    .map_err(|_| crypto_error("failed to encrypt key file"))?;
+    output.extend_from_slice(&nonce);
+    output.extend_from_slice(&in_out[..len]);
+    Ok(output)
+}
+
+/// Decrypts a file written by [`encrypt_key_file`]. The plaintext is wiped
+/// once dropped.
+pub(crate) fn decrypt_key_file(data: &[u8], passphrase: &str) -> Result<SecretBytes, Error> {
+    if data.len() < HEADER_LEN + NONCE_LEN + AES_256_GCM.tag_len() || !data.starts_with(MAGIC) {
+        return Err(Error::InvalidKey(
+            "not an encrypted key file, or an unsupported version".to_owned(),
+        ));
+    }
+    let (header, rest) = data.split_at(HEADER_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let mut iterations = [0; 4];
+    iterations.copy_from_slice(&header[MAGIC.len()..MAGIC.len() + 4]);
+    let iterations = u32::from_be_bytes(iterations);
+    if iterations == 0 {
+        return Err(Error::InvalidKey(
+            "invalid PBKDF2 iteration count".to_owned(),
+        ));
+    }
+    let key = derive_key(passphrase, &header[MAGIC.len() + 4..], iterations);
+    let key = OpeningKey::new(&AES_256_GCM, &key)
+        .map_err(|_| crypto_error("failed to create decryption key"))?;
+
+    let mut plaintext = SecretBytes::new(ciphertext.to_vec());
+    let len = aead::open_in_place(&key, nonce, header, 0, &mut plaintext)
+        .map_err(|_| {
+            Error::InvalidKey("wrong passphrase, or the key file is corrupted".to_owned())
+        })?
+        .len();
+    Ok(SecretBytes::new(plaintext[..len].to_vec()))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> SecretBytes {
+    let mut key = SecretBytes::new(vec![0; KEY_LEN]);
+    pbkdf2::derive(&SHA256, iterations, salt, passphrase.as_bytes(), &mut key);
+    key
+}
+
+fn crypto_error(message: &str) -> Error {
+    Error::Io(std::io::Error::other(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON: &[u8] = br#"{"type": "service_account"}"#;
+
+    fn assert_invalid_key(result: Result<SecretBytes, Error>, reason: &str) {
+        match result {
+            Err(Error::InvalidKey(message)) => assert!(message.contains(reason), "{}", message),
+            Err(err) => panic!("expected an invalid key, got {:?}", err),
+            Ok(_) => panic!("expected an invalid key, got a decrypted file"),
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let encrypted = encrypt_key_file(JSON, "passphrase").unwrap();
+        assert!(encrypted.starts_with(MAGIC));
+        assert_eq!(&*decrypt_key_file(&encrypted, "passphrase").unwrap(), JSON);
+        assert_invalid_key(decrypt_key_file(&encrypted, "wrong"), "wrong passphrase");
+    }
+
+    #[test]
+    fn authenticates_the_header() {
+        let mut encrypted = encrypt_key_file(JSON, "passphrase").unwrap();
+        // The last salt byte.
+        encrypted[HEADER_LEN - 1] ^= 1;
+        assert_invalid_key(
+            decrypt_key_file(&encrypted, "passphrase"),
+            "wrong passphrase",
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let encrypted = encrypt_key_file(JSON, "passphrase").unwrap();
+        let min_len = HEADER_LEN + NONCE_LEN + AES_256_GCM.tag_len();
+        for len in &[0, MAGIC.len(), min_len - 1] {
+            assert_invalid_key(
+                decrypt_key_file(&encrypted[..*len], "passphrase"),
+                "not an encrypted key file",
+            );
+        }
+        // Cut inside the ciphertext, which fails to authenticate.
+        assert_invalid_key(
+            decrypt_key_file(&encrypted[..encrypted.len() - 1], "passphrase"),
+            "wrong passphrase",
+        );
+
+        let mut zero_iterations = encrypted.clone();
+        zero_iterations[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&[0; 4]);
+        assert_invalid_key(
+            decrypt_key_file(&zero_iterations, "passphrase"),
+            "iteration count",
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use http::StatusCode;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, SystemTimeError};
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    InvalidKey(String),
+    InvalidCredentials(String),
+    CredentialsNotFound,
+    #[cfg(feature = "reqwest")]
+    Http(reqwest::Error),
+    #[cfg(feature = "reqwest")]
+    Timeout(reqwest::Error),
+    #[cfg(feature = "hyper")]
+    Hyper(hyper::Error),
+    Status {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    OAuth {
+        error: String,
+        error_description: Option<String>,
+    },
+    ClockSkew(String),
+    #[cfg(feature = "jsonwebtoken")]
+    JwtEncoding(jsonwebtoken::errors::Error),
+    Json(serde_json::Error),
+    Io(io::Error),
+    Clock(SystemTimeError),
+    Shared(Arc<Error>),
+    CircuitOpen,
+    InvalidToken(String),
+    /// A refresh failed recently, so no new request is sent for `retry_in`.
+    Throttled {
+        retry_in: Duration,
+    },
+    DeadlineExceeded,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidKey(reason) => write!(f, "invalid private key: {}", reason),
+            Error::InvalidCredentials(reason) => write!(f, "invalid credentials: {}", reason),
+            Error::CredentialsNotFound => {
+                write!(f, "could not find application default credentials")
+            }
+            #[cfg(feature = "reqwest")]
+            Error::Http(err) => write!(f, "http error: {}", err),
+            #[cfg(feature = "reqwest")]
+            Error::Timeout(err) => write!(f, "request timed out: {}", err),
+            #[cfg(feature = "hyper")]
+            Error::Hyper(err) => write!(f, "http error: {}", err),
+            Error::Status { status, .. } => write!(f, "unexpected response status: {}", status),
+            Error::OAuth {
+                error,
+                error_description: Some(description),
+            } => write!(f, "oauth error {}: {}", error, description),
+            Error::OAuth { error, .. } => write!(f, "oauth error {}", error),
+            Error::ClockSkew(description) => write!(
+                f,
+                "assertion rejected, check your system clock: {}",
+                description
+            ),
+            #[cfg(feature = "jsonwebtoken")]
+            Error::JwtEncoding(err) => write!(f, "failed to encode jwt: {}", err),
+            Error::Json(err) => write!(f, "failed to parse json: {}", err),
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::Clock(err) => write!(f, "system clock error: {}", err),
+            Error::Shared(err) => err.fmt(f),
+            Error::CircuitOpen => write!(f, "token endpoint circuit breaker is open"),
+            Error::InvalidToken(reason) => write!(f, "invalid token: {}", reason),
+            Error::Throttled { retry_in } => write!(
+                f,
+                "token refresh failed recently, next attempt allowed in {:?}",
+                retry_in
+            ),
+            Error::DeadlineExceeded => write!(f, "token fetch deadline exceeded"),
+        }
+    }
+}
+
+impl Error {
+    /// Whether sending the same request again may succeed: timeouts,
+    /// connection failures, 5xx and 429 responses and the like.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            #[cfg(feature = "reqwest")]
+            Error::Http(err) => {
+                err.is_timeout()
+                    || err.status().is_some_and(is_retryable_status)
+                    || err.get_ref().is_some_and(|inner| {
+                        // Transport failures surface as hyper or I/O errors;
+                        // `http::Error`s come from building the request.
+                        inner.is::<io::Error>() || (err.is_http() && !inner.is::<http::Error>())
+                    })
+            }
+            #[cfg(feature = "reqwest")]
+            Error::Timeout(_) => true,
+            #[cfg(feature = "hyper")]
+            Error::Hyper(err) => err.is_connect() || err.is_closed() || err.is_incomplete_message(),
+            Error::Status { status, .. } => is_retryable_status(*status),
+            Error::OAuth { error, .. } => is_retryable_oauth_error(error),
+            Error::Io(err) => matches!(
+                err.kind(),
+                io::ErrorKind::TimedOut
+                    | io::ErrorKind::Interrupted
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            Error::Shared(err) => err.is_retryable(),
+            Error::DeadlineExceeded => true,
+            _ => false,
+        }
+    }
+
+    /// Whether retrying cannot help until the credentials or configuration
+    /// change, e.g. a rejected grant or an invalid key.
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            Error::InvalidKey(_)
+            | Error::InvalidCredentials(_)
+            | Error::CredentialsNotFound
+            | Error::InvalidToken(_) => true,
+            #[cfg(feature = "jsonwebtoken")]
+            Error::JwtEncoding(_) => true,
+            Error::Status { status, .. } => {
+                status.is_client_error()
+                    && *status != StatusCode::REQUEST_TIMEOUT
+                    && *status != StatusCode::TOO_MANY_REQUESTS
+            }
+            Error::OAuth { error, .. } => !is_retryable_oauth_error(error),
+            Error::Shared(err) => err.is_permanent(),
+            _ => false,
+        }
+    }
+
+    /// A short, stable label for the kind of error, suitable for metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::InvalidKey(_) => "invalid_key",
+            Error::InvalidCredentials(_) => "invalid_credentials",
+            Error::CredentialsNotFound => "credentials_not_found",
+            #[cfg(feature = "reqwest")]
+            Error::Http(_) => "http",
+            #[cfg(feature = "reqwest")]
+            Error::Timeout(_) => "timeout",
+            #[cfg(feature = "hyper")]
+            Error::Hyper(_) => "http",
+            Error::Status { .. } => "status",
+            Error::OAuth { .. } => "oauth",
+            Error::ClockSkew(_) => "clock_skew",
+            #[cfg(feature = "jsonwebtoken")]
+            Error::JwtEncoding(_) => "jwt_encoding",
+            Error::Json(_) => "json",
+            Error::Io(_) => "io",
+            Error::Clock(_) => "clock",
+            Error::Shared(err) => err.kind(),
+            Error::CircuitOpen => "circuit_open",
+            Error::InvalidToken(_) => "invalid_token",
+            Error::Throttled { .. } => "throttled",
+            Error::DeadlineExceeded => "deadline_exceeded",
+        }
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+fn is_retryable_oauth_error(error: &str) -> bool {
+    error == "temporarily_unavailable" || error == "server_error"
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            #[cfg(feature = "reqwest")]
+            Error::Http(err) => Some(err),
+            #[cfg(feature = "reqwest")]
+            Error::Timeout(err) => Some(err),
+            #[cfg(feature = "hyper")]
+            Error::Hyper(err) => Some(err),
+            #[cfg(feature = "jsonwebtoken")]
+            Error::JwtEncoding(err) => Some(err),
+            Error::Json(err) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::Clock(err) => Some(err),
+            Error::Shared(err) => err.source(),
+            Error::InvalidKey(_)
+            | Error::InvalidCredentials(_)
+            | Error::CredentialsNotFound
+            | Error::Status { .. }
+            | Error::OAuth { .. }
+            | Error::ClockSkew(_)
+            | Error::CircuitOpen
+            | Error::InvalidToken(_)
+            | Error::Throttled { .. }
+            | Error::DeadlineExceeded => None,
+        }
+    }
+}
+
+#[cfg(feature = "openssl")]
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(err: openssl::error::ErrorStack) -> Self {
+        Error::InvalidKey(err.to_string())
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Error::Timeout(err)
+        } else {
+            Error::Http(err)
+        }
+    }
+}
+
+#[cfg(feature = "hyper")]
+impl From<hyper::Error> for Error {
+    fn from(err: hyper::Error) -> Self {
+        Error::Hyper(err)
+    }
+}
+
+#[cfg(feature = "jsonwebtoken")]
+impl From<jsonwebtoken::errors::Error> for Error {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        Error::JwtEncoding(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<SystemTimeError> for Error {
+    fn from(err: SystemTimeError) -> Self {
+        Error::Clock(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oauth(error: &str) -> Error {
+        Error::OAuth {
+            error: error.to_owned(),
+            error_description: None,
+        }
+    }
+
+    #[test]
+    fn classifies_oauth_errors() {
+        for code in ["temporarily_unavailable", "server_error"] {
+            assert!(oauth(code).is_retryable(), "{}", code);
+            assert!(!oauth(code).is_permanent(), "{}", code);
+        }
+        assert!(!oauth("invalid_grant").is_retryable());
+        assert!(oauth("invalid_grant").is_permanent());
+    }
+
+    #[test]
+    fn does_not_retry_local_rejections() {
+        assert!(!Error::CircuitOpen.is_retryable());
+        let throttled = Error::Throttled {
+            retry_in: Duration::from_secs(1),
+        };
+        assert!(!throttled.is_retryable());
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[test]
+    fn classifies_http_errors() {
+        let http = reqwest::Client::new();
+
+        let err = Error::from(http.get("not a url").send().unwrap_err());
+        assert!(!err.is_retryable());
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let err = Error::from(http.get(&url).send().unwrap_err());
+        assert!(err.is_retryable(), "{:?}", err);
+    }
+}
@@ -0,0 +1,271 @@
+use crate::aws::AwsTokenSupplier;
+use crate::cache::CachedToken;
+use crate::sts::sts_token_url;
+use crate::{
+    collect_scopes, default_http_client, AccessToken, Error, FileTokenSupplier, ImpersonatedClient,
+    Scope, StsClient, SubjectTokenFormat, SubjectTokenSupplier, TokenExchangeRequest,
+    TokenProvider, UrlTokenSupplier, DEFAULT_UNIVERSE_DOMAIN,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// How long STS tokens last when the response leaves out `expires_in`; STS
+/// issues them for an hour.
+const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Deserialize, Debug)]
+struct ExternalAccountConfig {
+    audience: String,
+    subject_token_type: String,
+    token_url: Option<String>,
+    service_account_impersonation_url: Option<String>,
+    credential_source: CredentialSource,
+    quota_project_id: Option<String>,
+    universe_domain: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CredentialSource {
+    environment_id: Option<String>,
+    region_url: Option<String>,
+    regional_cred_verification_url: Option<String>,
+    imdsv2_session_token_url: Option<String>,
+    file: Option<String>,
+    url: Option<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    format: Option<CredentialFormat>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum CredentialFormat {
+    Text,
+    Json { subject_token_field_name: String },
+}
+
+pub struct ExternalAccountClient {
+    source: ExternalSource,
+    quota_project_id: Option<String>,
+}
+
+#[allow(clippy::large_enum_variant)]
+enum ExternalSource {
+    Sts(StsExchange),
+    Impersonated(ImpersonatedClient<StsExchange>),
+}
+
+struct StsExchange {
+    audience: String,
+    subject_token_type: String,
+    sts: StsClient,
+    supplier: Arc<dyn SubjectTokenSupplier>,
+    scopes: Vec<String>,
+    universe_domain: String,
+    access_token: CachedToken,
+}
+
+impl ExternalAccountClient {
+    /// Exchanges the tokens of `supplier` for access tokens of the workload
+    /// identity pool provider named by `audience`.
+    pub fn new(
+        audience: impl Into<String>,
+        subject_token_type: impl Into<String>,
+        supplier: Arc<dyn SubjectTokenSupplier>,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> ExternalAccountClient {
+        let sts = StsExchange {
+            audience: audience.into(),
+            subject_token_type: subject_token_type.into(),
+            sts: StsClient::new(),
+            supplier,
+            scopes: collect_scopes(scopes),
+            universe_domain: DEFAULT_UNIVERSE_DOMAIN.to_owned(),
+            access_token: CachedToken::default(),
+        };
+        ExternalAccountClient {
+            source: ExternalSource::Sts(sts),
+            quota_project_id: None,
+        }
+    }
+
+    pub fn from_json_file(
+        path: impl AsRef<Path>,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<ExternalAccountClient, Error> {
+        let json = fs::read_to_string(path)?;
+        Self::from_json_str(&json, scopes)
+    }
+
+    pub fn from_json_str(
+        json: &str,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<ExternalAccountClient, Error> {
+        let config: ExternalAccountConfig = serde_json::from_str(json)?;
+        let supplier = supplier(config.credential_source, &config.audience)?;
+        let mut client = Self::new(config.audience, config.subject_token_type, supplier, scopes);
+        if let Some(universe_domain) = config.universe_domain {
+            client.sts_mut().universe_domain = universe_domain;
+        }
+        let token_url = config
+            .token_url
+            .unwrap_or_else(|| sts_token_url(&client.sts_mut().universe_domain));
+        client = client.token_url(token_url);
+        if let Some(url) = config.service_account_impersonation_url {
+            client = client.impersonate(impersonation_target(&url)?);
+        }
+        client.quota_project_id = config.quota_project_id;
+        Ok(client)
+    }
+
+    pub fn token_url(mut self, token_url: impl Into<String>) -> Self {
+        self.sts_mut().sts = StsClient::new().token_url(token_url);
+        self
+    }
+
+    fn sts_mut(&mut self) -> &mut StsExchange {
+        match &mut self.source {
+            ExternalSource::Sts(sts) => sts,
+            ExternalSource::Impersonated(impersonated) => impersonated.source_mut(),
+        }
+    }
+
+    /// Impersonates `target` with the federated token, as required by APIs
+    /// that don't accept federated identities directly.
+    pub fn impersonate(self, target: impl Into<String>) -> Self {
+        let source = match self.source {
+            ExternalSource::Sts(mut sts) => {
+                let scopes =
+                    std::mem::replace(&mut sts.scopes, vec![Scope::CloudPlatform.to_string()]);
+                ExternalSource::Impersonated(ImpersonatedClient::new(sts, target, scopes))
+            }
+            source => source,
+        };
+        ExternalAccountClient { source, ..self }
+    }
+
+    pub fn get_token(&self) -> Result<AccessToken, Error> {
+        match &self.source {
+            ExternalSource::Sts(sts) => sts.get_token(),
+            ExternalSource::Impersonated(impersonated) => impersonated.get_token(),
+        }
+    }
+
+    pub fn invalidate(&self) {
+        match &self.source {
+            ExternalSource::Sts(sts) => sts.invalidate(),
+            ExternalSource::Impersonated(impersonated) => impersonated.invalidate(),
+        }
+    }
+
+    pub fn quota_project_id(&self) -> Option<&str> {
+        self.quota_project_id.as_deref()
+    }
+}
+
+impl TokenProvider for ExternalAccountClient {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        ExternalAccountClient::get_token(self)
+    }
+
+    fn invalidate(&self) {
+        ExternalAccountClient::invalidate(self)
+    }
+
+    fn quota_project_id(&self) -> Option<&str> {
+        ExternalAccountClient::quota_project_id(self)
+    }
+
+    fn universe_domain(&self) -> &str {
+        match &self.source {
+            ExternalSource::Sts(sts) => sts.universe_domain(),
+            ExternalSource::Impersonated(impersonated) => impersonated.universe_domain(),
+        }
+    }
+}
+
+impl StsExchange {
+    fn fetch_token(&self) -> Result<AccessToken, Error> {
+        let subject_token = self.supplier.subject_token()?;
+        let request = TokenExchangeRequest::new(subject_token, &*self.subject_token_type)
+            .audience(&*self.audience)
+            .scopes(&self.scopes);
+        let response = self.sts.exchange(&request)?;
+        Ok(response.into_access_token(SystemTime::now() + DEFAULT_TOKEN_LIFETIME))
+    }
+}
+
+impl TokenProvider for StsExchange {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        self.access_token.get_or_fetch(|| self.fetch_token())
+    }
+
+    fn invalidate(&self) {
+        self.access_token.invalidate();
+    }
+
+    fn universe_domain(&self) -> &str {
+        &self.universe_domain
+    }
+}
+
+fn impersonation_target(url: &str) -> Result<&str, Error> {
+    url.rsplit('/')
+        .next()
+        .and_then(|name| name.split(':').next())
+        .filter(|email| !email.is_empty())
+        .ok_or_else(|| {
+            Error::InvalidCredentials("invalid service account impersonation url".to_owned())
+        })
+}
+
+fn supplier(
+    source: CredentialSource,
+    audience: &str,
+) -> Result<Arc<dyn SubjectTokenSupplier>, Error> {
+    let format = match source.format {
+        Some(CredentialFormat::Json {
+            subject_token_field_name,
+        }) => SubjectTokenFormat::Json(subject_token_field_name),
+        Some(CredentialFormat::Text) | None => SubjectTokenFormat::Text,
+    };
+    let supplier: Arc<dyn SubjectTokenSupplier> =
+        match (source.environment_id, source.file, source.url) {
+            (Some(environment_id), _, url) => match source.regional_cred_verification_url {
+                Some(verification_url) if environment_id.starts_with("aws") => {
+                    Arc::new(AwsTokenSupplier {
+                        region_url: source.region_url,
+                        credentials_url: url,
+                        verification_url,
+                        imdsv2_session_token_url: source.imdsv2_session_token_url,
+                        audience: audience.to_owned(),
+                        http: default_http_client(),
+                    })
+                }
+                _ => {
+                    return Err(Error::InvalidCredentials(format!(
+                        "unsupported external account environment {:?}",
+                        environment_id
+                    )))
+                }
+            },
+            (None, Some(file), _) => Arc::new(FileTokenSupplier::new(file).format(format)),
+            (None, None, Some(url)) => {
+                let mut supplier = UrlTokenSupplier::new(url).format(format);
+                for (name, value) in source.headers {
+                    supplier = supplier.header(name, value);
+                }
+                Arc::new(supplier)
+            }
+            (None, None, None) => {
+                return Err(Error::InvalidCredentials(
+                    "unsupported external account credential source".to_owned(),
+                ))
+            }
+        };
+    Ok(supplier)
+}
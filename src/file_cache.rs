@@ -0,0 +1,213 @@
+use crate::{AccessToken, TokenCache};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize)]
+struct CachedFile {
+    value: String,
+    expires: u64,
+}
+
+/// A [`TokenCache`] that persists tokens on disk so they can be reused across
+/// process restarts. Files are only readable by the current user and are
+/// replaced atomically. I/O errors are treated as cache misses.
+pub struct FileTokenCache {
+    dir: PathBuf,
+}
+
+impl FileTokenCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileTokenCache { dir: dir.into() }
+    }
+
+    /// Uses `$XDG_CACHE_HOME/google-token-provider`, falling back to
+    /// `~/.cache/google-token-provider`.
+    pub fn default_dir() -> Option<PathBuf> {
+        let cache_dir = match env::var_os("XDG_CACHE_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        };
+        Some(cache_dir.join("google-token-provider"))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        key_path(&self.dir, key)
+    }
+}
+
+/// The file in `dir` for `key`, named by its hash.
+pub(crate) fn key_path(dir: &Path, key: &str) -> PathBuf {
+    let hash = digest::digest(&digest::SHA256, key.as_bytes());
+    let name: String = hash
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    dir.join(name + ".json")
+}
+
+/// Atomically replaces `path` in `dir` with a file only the current user
+/// can read.
+pub(crate) fn write_private(dir: &Path, path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    create_private_dir(dir)?;
+    let (tmp, mut out) = temp_file(path)?;
+    let written = out.write_all(contents).and_then(|_| out.sync_all());
+    let renamed = written.and_then(|_| fs::rename(&tmp, path));
+    if renamed.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    renamed
+}
+
+/// Creates a new file next to `path`, named uniquely so concurrent writers,
+/// whether threads or processes, never share one.
+fn temp_file(path: &Path) -> std::io::Result<(PathBuf, fs::File)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let tmp = path.with_extension(format!(
+            "tmp{}.{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        match private_file(&tmp) {
+            Ok(file) => return Ok((tmp, file)),
+            // Left behind by an earlier process with the same pid.
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+impl TokenCache for FileTokenCache {
+    fn get(&self, key: &str) -> Option<AccessToken> {
+        let json = fs::read(self.path(key)).ok()?;
+        let file: CachedFile = serde_json::from_slice(&json).ok()?;
+        let token = AccessToken::new(file.value, UNIX_EPOCH + Duration::from_secs(file.expires));
+        Some(token).filter(|token| !token.expired())
+    }
+
+    fn put(&self, key: &str, token: AccessToken) {
+        let expires = match token.expires.duration_since(UNIX_EPOCH) {
+            Ok(expires) => expires.as_secs(),
+            Err(_) => return,
+        };
+        if token.expires <= SystemTime::now() {
+            return;
+        }
+        let file = CachedFile {
+            value: token.value.clone(),
+            expires,
+        };
+        if let Ok(contents) = serde_json::to_vec(&file) {
+            let _ = write_private(&self.dir, &self.path(key), &contents);
+        }
+    }
+
+    fn invalidate(&self, key: &str) {
+        let _ = fs::remove_file(self.path(key));
+    }
+}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+#[cfg(unix)]
+fn private_file(path: &Path) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn private_file(path: &Path) -> std::io::Result<fs::File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn concurrent_writers_do_not_share_temp_files() {
+        let dir = Arc::new(env::temp_dir().join(format!("file-cache-{}", std::process::id())));
+        let path = Arc::new(dir.join("token.json"));
+        let writers: Vec<_> = (0..8)
+            .map(|n| {
+                let (dir, path) = (dir.clone(), path.clone());
+                thread::spawn(move || {
+                    let contents = vec![b'0' + n; 4096];
+                    (0..20).all(|_| write_private(&dir, &path, &contents).is_ok())
+                })
+            })
+            .collect();
+        for writer in writers {
+            assert!(writer.join().unwrap());
+        }
+
+        let contents = fs::read(&*path).unwrap();
+        assert_eq!(contents.len(), 4096);
+        assert!(contents.iter().all(|&b| b == contents[0]));
+        assert_eq!(fs::read_dir(&*dir).unwrap().count(), 1);
+        fs::remove_dir_all(&*dir).unwrap();
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn keeps_tokens_of_other_keys_and_endpoints_apart() {
+        use crate::{Client, Credentials, Error, MockResponse, MockTokenServer};
+
+        let email = "test@example-project.iam.gserviceaccount.com";
+        let key = Credentials::from_pem(include_str!("../testdata/rsa_pkcs1.pem"), email).unwrap();
+        let other_key =
+            Credentials::from_pem(include_str!("../testdata/other_rsa_pkcs1.pem"), email).unwrap();
+        let dir = env::temp_dir().join(format!("file-cache-keys-{}", std::process::id()));
+        let cache: Arc<dyn TokenCache> = Arc::new(FileTokenCache::new(&dir));
+        let client = |credentials: &Credentials, server: &MockTokenServer| {
+            Client::builder(credentials.clone(), ["scope"])
+                .token_url(server.token_url())
+                .token_cache(cache.clone())
+                .build()
+                .unwrap()
+        };
+
+        let server = MockTokenServer::start(key.clone()).unwrap();
+        server.respond_with(MockResponse::token("token-1", 3600));
+        assert_eq!(client(&key, &server).get_token().unwrap().value, "token-1");
+
+        // Not served the first key's token from the cache.
+        let err = client(&other_key, &server).get_token().unwrap_err();
+        assert!(
+            matches!(&err, Error::OAuth { error, .. } if error == "invalid_grant"),
+            "{}",
+            err
+        );
+
+        let other_server = MockTokenServer::start(key.clone()).unwrap();
+        other_server.respond_with(MockResponse::token("token-2", 3600));
+        let token = client(&key, &other_server).get_token().unwrap();
+        assert_eq!(token.value, "token-2");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use crate::cache::CachedToken;
+use crate::transport::form_body;
+use crate::{
+    collect_scopes, create_jwt, default_headers, AccessToken, Claims, Credentials, Error,
+    HttpResponse, TokenResponse, GRANT_TYPE,
+};
+use futures::future::{self, Either, Future};
+use futures::Stream;
+use http::header::CONTENT_TYPE;
+use http::{HeaderValue, Request};
+use hyper::client::connect::Connect;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client as HTTPClient};
+use hyper_tls::HttpsConnector;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A service account client on hyper alone, for builds without reqwest.
+#[derive(Clone)]
+pub struct HyperClient<C = HttpsConnector<HttpConnector>> {
+    credentials: Credentials,
+    scopes: String,
+    token_url: String,
+    subject: Option<String>,
+    http: HTTPClient<C>,
+    access_token: Arc<CachedToken>,
+}
+
+impl HyperClient {
+    pub fn new(
+        credentials: Credentials,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> HyperClient {
+        let connector = HttpsConnector::new(1).expect("failed to initialize TLS");
+        Self::with_http_client(credentials, scopes, HTTPClient::builder().build(connector))
+    }
+}
+
+impl<C: Connect + 'static> HyperClient<C> {
+    pub fn with_http_client(
+        credentials: Credentials,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+        http: HTTPClient<C>,
+    ) -> HyperClient<C> {
+        HyperClient {
+            token_url: credentials.token_uri().to_owned(),
+            credentials,
+            scopes: collect_scopes(scopes).join(" "),
+            subject: None,
+            http,
+            access_token: Arc::new(CachedToken::default()),
+        }
+    }
+
+    pub fn token_url(mut self, token_url: impl Into<String>) -> Self {
+        self.token_url = token_url.into();
+        self
+    }
+
+    pub fn subject(mut self, subject: impl Into<String>) -> Self {
+        self.subject = Some(subject.into());
+        self
+    }
+
+    pub fn refresh_margin(mut self, margin: Duration) -> Self {
+        self.access_token = Arc::new(CachedToken::new(margin));
+        self
+    }
+
+    pub fn get_token(&self) -> impl Future<Item = AccessToken, Error = Error> + Send {
+        if let Some(token) = self.access_token.get() {
+            return Either::A(future::ok(token));
+        }
+        let access_token = self.access_token.clone();
+        Either::B(self.fetch_token().map(move |token| {
+            access_token.set(token.clone());
+            token
+        }))
+    }
+
+    pub fn invalidate(&self) {
+        self.access_token.invalidate();
+    }
+
+    fn fetch_token(&self) -> impl Future<Item = AccessToken, Error = Error> + Send {
+        let request = match self.token_request() {
+            Ok(request) => request,
+            Err(err) => return Either::A(future::err(err)),
+        };
+        Either::B(
+            self.http
+                .request(request)
+                .and_then(|response| {
+                    let (parts, body) = response.into_parts();
+                    body.concat2().map(move |body| (parts, body))
+                })
+                .from_err()
+                .and_then(|(parts, body)| {
+                    HttpResponse {
+                        status: parts.status,
+                        headers: parts.headers,
+                        body: body.to_vec(),
+                        remote_addr: None,
+                    }
+                    .json::<TokenResponse>()
+                })
+                .map(AccessToken::from),
+        )
+    }
+
+    fn token_request(&self) -> Result<Request<Body>, Error> {
+        let claims = Claims {
+            scope: Some(self.scopes.clone()),
+            sub: self.subject.clone(),
+            ..Claims::new(&self.credentials, &self.token_url)?
+        };
+        let assertion = create_jwt(&self.credentials, &claims)?;
+        let body = form_body(&[("grant_type", GRANT_TYPE), ("assertion", &assertion)]);
+        let mut request = Request::post(self.token_url.as_str())
+            .body(Body::from(body))
+            .map_err(|_| {
+                Error::InvalidCredentials(format!("invalid token url {:?}", self.token_url))
+            })?;
+        let headers = request.headers_mut();
+        headers.extend(default_headers());
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        Ok(request)
+    }
+}
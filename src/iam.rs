@@ -0,0 +1,107 @@
+use crate::impersonated::iam_credentials_url;
+use crate::{default_headers, default_http_client, parse_response, Error, TokenProvider};
+use reqwest::Client as HTTPClient;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Serialize, Debug)]
+struct SignJwtRequest {
+    payload: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SignJwtResponse {
+    signed_jwt: String,
+}
+
+#[derive(Serialize, Debug)]
+struct SignBlobRequest {
+    payload: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SignBlobResponse {
+    signed_blob: String,
+}
+
+/// Signs assertions for a service account through the IAM Credentials API,
+/// so no private key is ever held locally.
+pub struct IamSigner {
+    email: String,
+    provider: Arc<dyn TokenProvider>,
+    http: HTTPClient,
+}
+
+impl IamSigner {
+    /// `provider` authorizes the signJwt calls, e.g. a `MetadataClient`, and
+    /// needs `roles/iam.serviceAccountTokenCreator` on `email`.
+    pub fn new(email: impl Into<String>, provider: Arc<dyn TokenProvider>) -> Self {
+        IamSigner {
+            email: email.into(),
+            provider,
+            http: default_http_client(),
+        }
+    }
+
+    pub fn http_client(mut self, http: HTTPClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub(crate) fn sign_jwt(&self, claims: &impl Serialize) -> Result<String, Error> {
+        let token = self.provider.get_token()?;
+        let request = SignJwtRequest {
+            payload: serde_json::to_string(claims)?,
+        };
+        let response = self
+            .http
+            .post(&format!(
+                "{}/{}:signJwt",
+                iam_credentials_url(self.provider.universe_domain()),
+                self.email
+            ))
+            .headers(default_headers())
+            .bearer_auth(&token.value)
+            .json(&request)
+            .send()?;
+        Ok(parse_response::<SignJwtResponse>(response)?.signed_jwt)
+    }
+
+    /// Signs with RSA-SHA256, using one of the service account's Google
+    /// managed keys.
+    pub(crate) fn sign_blob(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let token = self.provider.get_token()?;
+        let request = SignBlobRequest {
+            payload: base64::encode(message),
+        };
+        let response = self
+            .http
+            .post(&format!(
+                "{}/{}:signBlob",
+                iam_credentials_url(self.provider.universe_domain()),
+                self.email
+            ))
+            .headers(default_headers())
+            .bearer_auth(&token.value)
+            .json(&request)
+            .send()?;
+        let signed = parse_response::<SignBlobResponse>(response)?.signed_blob;
+        base64::decode(&signed)
+            .map_err(|err| Error::InvalidKey(format!("invalid signBlob signature: {}", err)))
+    }
+}
+
+impl fmt::Debug for IamSigner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IamSigner")
+            .field("email", &self.email)
+            .finish()
+    }
+}
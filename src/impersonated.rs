@@ -0,0 +1,141 @@
+use crate::cache::CachedToken;
+use crate::{
+    collect_scopes, default_headers, default_http_client, parse_response, AccessToken, Error,
+    TokenProvider,
+};
+use reqwest::Client as HTTPClient;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+/// The IAM Credentials API's service account collection in a universe.
+pub(crate) fn iam_credentials_url(universe_domain: &str) -> String {
+    format!(
+        "https://iamcredentials.{}/v1/projects/-/serviceAccounts",
+        universe_domain
+    )
+}
+
+#[derive(Serialize, Debug)]
+struct GenerateAccessTokenRequest<'a> {
+    delegates: &'a [String],
+    scope: &'a [String],
+    lifetime: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GenerateAccessTokenResponse {
+    access_token: String,
+}
+
+pub struct ImpersonatedClient<P> {
+    source: P,
+    target: String,
+    delegates: Vec<String>,
+    scopes: Vec<String>,
+    lifetime: Duration,
+    http: HTTPClient,
+    access_token: CachedToken,
+}
+
+impl<P: TokenProvider> ImpersonatedClient<P> {
+    pub fn new(
+        source: P,
+        target: impl Into<String>,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> ImpersonatedClient<P> {
+        ImpersonatedClient {
+            source,
+            target: target.into(),
+            delegates: Vec::new(),
+            scopes: collect_scopes(scopes),
+            lifetime: Duration::from_secs(60 * 60),
+            http: default_http_client(),
+            access_token: CachedToken::default(),
+        }
+    }
+
+    pub fn delegates(mut self, delegates: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        self.delegates = delegates
+            .into_iter()
+            .map(|delegate| service_account_name(delegate.as_ref()))
+            .collect();
+        self
+    }
+
+    pub fn http_client(mut self, http: HTTPClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    pub(crate) fn source_mut(&mut self) -> &mut P {
+        &mut self.source
+    }
+
+    pub fn get_token(&self) -> Result<AccessToken, Error> {
+        self.access_token.get_or_fetch(|| self.fetch_token())
+    }
+
+    pub fn force_refresh(&self) -> Result<AccessToken, Error> {
+        self.access_token.refresh(|| self.fetch_token())
+    }
+
+    pub fn invalidate(&self) {
+        self.access_token.invalidate();
+    }
+
+    fn fetch_token(&self) -> Result<AccessToken, Error> {
+        let source_token = self.source.get_token()?;
+        let request = GenerateAccessTokenRequest {
+            delegates: &self.delegates,
+            scope: &self.scopes,
+            lifetime: format!("{}s", self.lifetime.as_secs()),
+        };
+
+        let expires = SystemTime::now() + self.lifetime;
+        let response = self
+            .http
+            .post(&format!(
+                "{}/{}:generateAccessToken",
+                iam_credentials_url(self.source.universe_domain()),
+                self.target
+            ))
+            .headers(default_headers())
+            .bearer_auth(&source_token.value)
+            .json(&request)
+            .send()?;
+        let response = parse_response::<GenerateAccessTokenResponse>(response)?;
+        Ok(AccessToken::new(response.access_token, expires))
+    }
+}
+
+impl<P: TokenProvider> TokenProvider for ImpersonatedClient<P> {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        ImpersonatedClient::get_token(self)
+    }
+
+    fn invalidate(&self) {
+        ImpersonatedClient::invalidate(self)
+    }
+
+    fn force_refresh(&self) -> Result<AccessToken, Error> {
+        ImpersonatedClient::force_refresh(self)
+    }
+
+    fn quota_project_id(&self) -> Option<&str> {
+        self.source.quota_project_id()
+    }
+
+    fn universe_domain(&self) -> &str {
+        self.source.universe_domain()
+    }
+}
+
+fn service_account_name(email: &str) -> String {
+    format!("projects/-/serviceAccounts/{}", email)
+}
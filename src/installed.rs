@@ -0,0 +1,304 @@
+use crate::token_storage::{load_or_authorize, StorageSlot};
+use crate::user::USER_TOKEN_URL;
+use crate::{
+    collect_scopes, default_headers, default_http_client, parse_response, Error, TokenStorage,
+    UserCredentials,
+};
+use reqwest::{Client as HTTPClient, Url};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, TcpListener};
+use std::sync::Arc;
+
+const AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
+const RESPONSE_PAGE: &str =
+    "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nConnection: close\r\n\r\n\
+    <html><body>Authorization finished, you may close this window.</body></html>";
+
+const NOT_FOUND: &str = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+#[derive(Deserialize, Debug)]
+struct AuthorizationCodeResponse {
+    refresh_token: String,
+}
+
+/// The OAuth 2.0 flow for installed applications: the user consents in a
+/// browser, which is redirected back to a one-off listener on localhost.
+pub struct InstalledFlow {
+    client_id: String,
+    client_secret: String,
+    scopes: Vec<String>,
+    auth_url: String,
+    token_url: String,
+    http: HTTPClient,
+    storage: StorageSlot,
+}
+
+impl InstalledFlow {
+    pub fn new(
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> InstalledFlow {
+        InstalledFlow {
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            scopes: collect_scopes(scopes),
+            auth_url: AUTH_URL.to_owned(),
+            token_url: USER_TOKEN_URL.to_owned(),
+            http: default_http_client(),
+            storage: None,
+        }
+    }
+
+    pub fn auth_url(mut self, auth_url: impl Into<String>) -> Self {
+        self.auth_url = auth_url.into();
+        self
+    }
+
+    pub fn token_url(mut self, token_url: impl Into<String>) -> Self {
+        self.token_url = token_url.into();
+        self
+    }
+
+    pub fn http_client(mut self, http: HTTPClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Keeps the credentials in `storage` under `key`, so that `authorize`
+    /// only runs the flow when nothing is stored yet. Delete the entry to
+    /// authorize again, e.g. after the grant is revoked.
+    pub fn storage(mut self, storage: Arc<dyn TokenStorage>, key: impl Into<String>) -> Self {
+        self.storage = Some((storage, key.into()));
+        self
+    }
+
+    /// Runs the flow, passing the consent URL to `present`, which should open
+    /// it in a browser or print it. Blocks until the browser is redirected
+    /// back, then returns credentials holding the new refresh token.
+    pub fn authorize(
+        &self,
+        present: impl FnOnce(&str) -> Result<(), Error>,
+    ) -> Result<UserCredentials, Error> {
+        load_or_authorize(&self.storage, || self.run(present))
+    }
+
+    fn run(
+        &self,
+        present: impl FnOnce(&str) -> Result<(), Error>,
+    ) -> Result<UserCredentials, Error> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let redirect_uri = format!("http://{}", listener.local_addr()?);
+        let verifier = random_string(32)?;
+        let state = random_string(16)?;
+        let challenge = code_challenge(&verifier);
+
+        let url = Url::parse_with_params(
+            &self.auth_url,
+            &[
+                ("client_id", self.client_id.as_str()),
+                ("redirect_uri", &redirect_uri),
+                ("response_type", "code"),
+                ("scope", &self.scopes.join(" ")),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+                ("state", &state),
+                ("access_type", "offline"),
+                ("prompt", "consent"),
+            ],
+        )
+        .map_err(|_| Error::InvalidCredentials(format!("invalid auth url {:?}", self.auth_url)))?;
+        present(url.as_str())?;
+
+        let query = receive_redirect(&listener)?;
+        if let Some(error) = query.get("error") {
+            return Err(Error::OAuth {
+                error: error.clone(),
+                error_description: query.get("error_description").cloned(),
+            });
+        }
+        if query.get("state") != Some(&state) {
+            return Err(Error::OAuth {
+                error: "invalid_state".to_owned(),
+                error_description: Some("redirect state does not match the request".to_owned()),
+            });
+        }
+        let code = query.get("code").ok_or_else(|| Error::OAuth {
+            error: "invalid_request".to_owned(),
+            error_description: Some("redirect has no authorization code".to_owned()),
+        })?;
+
+        let mut params = HashMap::new();
+        params.insert("grant_type", "authorization_code");
+        params.insert("code", code);
+        params.insert("code_verifier", &verifier);
+        params.insert("client_id", &self.client_id);
+        params.insert("client_secret", &self.client_secret);
+        params.insert("redirect_uri", &redirect_uri);
+        let response = self
+            .http
+            .post(&self.token_url)
+            .headers(default_headers())
+            .form(&params)
+            .send()?;
+        let response = parse_response::<AuthorizationCodeResponse>(response)?;
+        Ok(UserCredentials::new(
+            self.client_id.clone(),
+            self.client_secret.clone(),
+            response.refresh_token,
+        ))
+    }
+}
+
+/// Accepts the browser's redirect and returns its query parameters, ignoring
+/// unrelated requests such as for a favicon.
+fn receive_redirect(listener: &TcpListener) -> Result<HashMap<String, String>, Error> {
+    loop {
+        let (mut stream, _) = listener.accept()?;
+        let mut request_line = String::new();
+        BufReader::new(&stream).read_line(&mut request_line)?;
+
+        let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+        let query: HashMap<String, String> = Url::parse(&format!("http://localhost{}", target))
+            .map(|url| url.query_pairs().into_owned().collect())
+            .unwrap_or_default();
+        if query.contains_key("state") || query.contains_key("error") {
+            stream.write_all(RESPONSE_PAGE.as_bytes())?;
+            return Ok(query);
+        }
+        stream.write_all(NOT_FOUND.as_bytes())?;
+    }
+}
+
+/// The S256 PKCE challenge for `verifier`.
+fn code_challenge(verifier: &str) -> String {
+    base64::encode_config(
+        digest(&SHA256, verifier.as_bytes()).as_ref(),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+fn random_string(len: usize) -> Result<String, Error> {
+    let mut bytes = vec![0; len];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| Error::Io(std::io::Error::other("failed to generate random bytes")))?;
+    Ok(base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpStream;
+    use std::thread;
+
+    /// Answers one token request, checking its PKCE verifier against the
+    /// challenge in the consent URL.
+    fn serve_token(challenge: Arc<std::sync::Mutex<String>>) -> String {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut length = 0;
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                line.clear();
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let form = query(&format!(
+                "http://localhost/?{}",
+                String::from_utf8_lossy(&body)
+            ));
+            let (status, body) =
+                if code_challenge(&form["code_verifier"]) == *challenge.lock().unwrap() {
+                    ("200 OK", r#"{"refresh_token": "refresh"}"#)
+                } else {
+                    ("400 Bad Request", r#"{"error": "invalid_grant"}"#)
+                };
+            let response = format!(
+                "HTTP/1.1 {}\r\ncontent-type: application/json\r\n\
+                 content-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        url
+    }
+
+    fn query(url: &str) -> HashMap<String, String> {
+        Url::parse(url)
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect()
+    }
+
+    /// Follows the consent URL's redirect as the browser would, replacing
+    /// the state when `state` is given.
+    fn redirect(url: &str, state: Option<&str>) -> Result<(), Error> {
+        let query = query(url);
+        let redirect_uri = Url::parse(&query["redirect_uri"]).unwrap();
+        let state = state.unwrap_or(&query["state"]).to_owned();
+        let address = format!(
+            "{}:{}",
+            redirect_uri.host_str().unwrap(),
+            redirect_uri.port().unwrap()
+        );
+        thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            write!(stream, "GET /?code=code&state={} HTTP/1.1\r\n\r\n", state).unwrap();
+            let _ = stream.read_to_end(&mut Vec::new());
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn computes_the_rfc_7636_challenge() {
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn exchanges_the_code_with_the_matching_verifier() {
+        let challenge = Arc::default();
+        let flow = InstalledFlow::new("id", "secret", ["scope"])
+            .token_url(serve_token(Arc::clone(&challenge)));
+        let credentials = flow
+            .authorize(|url| {
+                let query = query(url);
+                assert_eq!(query["code_challenge_method"], "S256");
+                *challenge.lock().unwrap() = query["code_challenge"].clone();
+                redirect(url, None)
+            })
+            .unwrap();
+        let credentials = serde_json::to_value(&credentials).unwrap();
+        assert_eq!(credentials["refresh_token"], "refresh");
+    }
+
+    #[test]
+    fn rejects_redirects_with_another_state() {
+        let flow = InstalledFlow::new("id", "secret", ["scope"]);
+        let err = flow
+            .authorize(|url| redirect(url, Some("forged")))
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::OAuth { error, .. } if error == "invalid_state"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,466 @@
+use crate::secret::SecretBytes;
+#[cfg(feature = "blocking")]
+use crate::IamSigner;
+use crate::{Error, Signer};
+#[cfg(feature = "openssl")]
+use openssl::{pkey::Private, rsa::Rsa};
+#[cfg(feature = "ring")]
+use ring::digest;
+#[cfg(feature = "ring")]
+use ring::rand::SystemRandom;
+#[cfg(feature = "ring")]
+use ring::signature::{
+    ECDSAKeyPair, RSAKeyPair, RSASigningState, ECDSA_P256_SHA256_FIXED_SIGNING, RSA_PKCS1_SHA256,
+};
+use serde::Serialize;
+use std::sync::Arc;
+
+const PKCS1_LABEL: &str = "RSA PRIVATE KEY";
+const PKCS8_LABEL: &str = "PRIVATE KEY";
+const RSA_ENCRYPTION_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const EC_PUBLIC_KEY_OID: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_EXPLICIT_VERSION: u8 = 0xa0;
+
+#[derive(Clone)]
+pub(crate) enum PrivateKey {
+    /// PKCS#1 DER encoded RSA key, signed with RS256.
+    Rsa(SecretBytes),
+    /// PKCS#8 DER encoded P-256 key, signed with ES256.
+    Ec(SecretBytes),
+    #[cfg(feature = "openssl")]
+    Openssl(Rsa<Private>),
+    /// An external signer, such as a Cloud KMS key.
+    Signer(Arc<dyn Signer>),
+    /// The IAM Credentials API, which picks the key and signs the whole JWT.
+    #[cfg(feature = "blocking")]
+    Iam(Arc<IamSigner>),
+}
+
+#[derive(Serialize)]
+struct JwtHeader<'a> {
+    typ: &'static str,
+    alg: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kid: Option<&'a str>,
+}
+
+impl PrivateKey {
+    pub(crate) fn from_pem(pem: &str) -> Result<Self, Error> {
+        let (label, body) = pem_body(pem)?;
+        let der = SecretBytes::new(
+            base64::decode(&*body)
+                .map_err(|err| invalid_key(format!("invalid base64: {}", err)))?,
+        );
+        match label {
+            PKCS1_LABEL => Self::from_pkcs1(der),
+            PKCS8_LABEL => Self::from_pkcs8(der),
+            label => Err(invalid_key(format!("unsupported PEM label {:?}", label))),
+        }
+    }
+
+    /// Accepts either a PKCS#1 RSAPrivateKey or a PKCS#8 PrivateKeyInfo.
+    pub(crate) fn from_der(der: SecretBytes) -> Result<Self, Error> {
+        let (info, _) = read_tlv(&der, TAG_SEQUENCE)?;
+        let (_version, rest) = read_tlv(info, TAG_INTEGER)?;
+        match rest.first() {
+            Some(&TAG_SEQUENCE) => Self::from_pkcs8(der),
+            Some(&TAG_INTEGER) => Self::from_pkcs1(der),
+            _ => Err(invalid_key("DER is neither a PKCS#1 nor a PKCS#8 key")),
+        }
+    }
+
+    fn from_pkcs1(der: SecretBytes) -> Result<Self, Error> {
+        rsa_key_pair(&der)?;
+        Ok(PrivateKey::Rsa(der))
+    }
+
+    fn from_pkcs8(der: SecretBytes) -> Result<Self, Error> {
+        let (info, _) = read_tlv(&der, TAG_SEQUENCE)?;
+        let (_version, rest) = read_tlv(info, TAG_INTEGER)?;
+        let (algorithm, rest) = read_tlv(rest, TAG_SEQUENCE)?;
+        let (oid, _) = read_tlv(algorithm, TAG_OID)?;
+        if oid == RSA_ENCRYPTION_OID {
+            let (private_key, _) = read_tlv(rest, TAG_OCTET_STRING)?;
+            Self::from_pkcs1(SecretBytes::new(private_key.to_vec()))
+        } else if oid == EC_PUBLIC_KEY_OID {
+            ec_key_pair(&der)?;
+            Ok(PrivateKey::Ec(der))
+        } else {
+            Err(invalid_key("PKCS#8 key is neither an RSA nor an EC key"))
+        }
+    }
+
+    pub(crate) fn sign_jwt(
+        &self,
+        kid: Option<&str>,
+        claims: &impl Serialize,
+    ) -> Result<String, Error> {
+        match self {
+            PrivateKey::Rsa(der) => sign_with("RS256", kid, claims, |input| sign_rs256(der, input)),
+            PrivateKey::Ec(der) => sign_with("ES256", kid, claims, |input| sign_es256(der, input)),
+            #[cfg(feature = "openssl")]
+            PrivateKey::Openssl(key) => {
+                let der = SecretBytes::new(key.private_key_to_der()?);
+                sign_with("RS256", kid, claims, |input| sign_rs256(&der, input))
+            }
+            PrivateKey::Signer(signer) => {
+                sign_with(signer.algorithm(), kid, claims, |input| signer.sign(input))
+            }
+            #[cfg(feature = "blocking")]
+            PrivateKey::Iam(signer) => signer.sign_jwt(claims),
+        }
+    }
+
+    /// The JWS name of the signature algorithm `sign_bytes` uses.
+    /// A short hash of a local key, to tell keys apart without exposing
+    /// them. `None` for keys held elsewhere.
+    #[cfg(feature = "ring")]
+    pub(crate) fn fingerprint(&self) -> Option<String> {
+        let hash = match self {
+            PrivateKey::Rsa(der) | PrivateKey::Ec(der) => digest::digest(&digest::SHA256, der),
+            #[cfg(feature = "openssl")]
+            PrivateKey::Openssl(key) => {
+                let der = SecretBytes::new(key.private_key_to_der().ok()?);
+                digest::digest(&digest::SHA256, &der)
+            }
+            _ => return None,
+        };
+        Some(
+            hash.as_ref()[..8]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        )
+    }
+
+    pub(crate) fn algorithm(&self) -> &str {
+        match self {
+            PrivateKey::Ec(_) => "ES256",
+            PrivateKey::Signer(signer) => signer.algorithm(),
+            _ => "RS256",
+        }
+    }
+
+    pub(crate) fn sign_bytes(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            PrivateKey::Rsa(der) => sign_rs256(der, message),
+            PrivateKey::Ec(der) => sign_es256(der, message),
+            #[cfg(feature = "openssl")]
+            PrivateKey::Openssl(key) => {
+                sign_rs256(&SecretBytes::new(key.private_key_to_der()?), message)
+            }
+            PrivateKey::Signer(signer) => signer.sign(message),
+            #[cfg(feature = "blocking")]
+            PrivateKey::Iam(signer) => signer.sign_blob(message),
+        }
+    }
+}
+
+#[cfg(feature = "ring")]
+fn sign_rs256(der: &[u8], message: &[u8]) -> Result<Vec<u8>, Error> {
+    let key_pair = Arc::new(rsa_key_pair(der)?);
+    let mut signature = vec![0; key_pair.public_modulus_len()];
+    RSASigningState::new(key_pair)
+        .and_then(|mut state| {
+            state.sign(
+                &RSA_PKCS1_SHA256,
+                &SystemRandom::new(),
+                message,
+                &mut signature,
+            )
+        })
+        .map_err(|_| invalid_key("failed to sign with RSA key"))?;
+    Ok(signature)
+}
+
+#[cfg(feature = "ring")]
+fn sign_es256(der: &[u8], message: &[u8]) -> Result<Vec<u8>, Error> {
+    let signature = ec_key_pair(der)?
+        .sign(untrusted::Input::from(message), &SystemRandom::new())
+        .map_err(|_| invalid_key("failed to sign with EC key"))?;
+    Ok(signature.as_ref().to_vec())
+}
+
+fn sign_with(
+    alg: &str,
+    kid: Option<&str>,
+    claims: &impl Serialize,
+    sign: impl FnOnce(&[u8]) -> Result<Vec<u8>, Error>,
+) -> Result<String, Error> {
+    let header = JwtHeader {
+        typ: "JWT",
+        alg,
+        kid,
+    };
+    let mut token = encode_part(&header)?;
+    token.push('.');
+    token.push_str(&encode_part(claims)?);
+
+    let signature = sign(token.as_bytes())?;
+    token.push('.');
+    token.push_str(&base64::encode_config(&signature, base64::URL_SAFE_NO_PAD));
+    Ok(token)
+}
+
+fn encode_part(part: &impl Serialize) -> Result<String, Error> {
+    let json = serde_json::to_vec(part)?;
+    Ok(base64::encode_config(&json, base64::URL_SAFE_NO_PAD))
+}
+
+#[cfg(feature = "ring")]
+fn rsa_key_pair(der: &[u8]) -> Result<RSAKeyPair, Error> {
+    RSAKeyPair::from_der(untrusted::Input::from(der))
+        .map_err(|_| invalid_key("not a valid PKCS#1 RSA key"))
+}
+
+#[cfg(feature = "ring")]
+fn ec_key_pair(der: &[u8]) -> Result<ECDSAKeyPair, Error> {
+    ECDSAKeyPair::from_pkcs8(
+        &ECDSA_P256_SHA256_FIXED_SIGNING,
+        untrusted::Input::from(der),
+    )
+    .map_err(|_| invalid_key("EC key is not a valid P-256 PKCS#8 key"))
+}
+
+// Without ring, keys can only sign through a `Signer`, e.g. one backed by
+// WebCrypto on wasm.
+#[cfg(not(feature = "ring"))]
+fn rsa_key_pair(_der: &[u8]) -> Result<(), Error> {
+    Err(invalid_key("local keys require the `ring` feature"))
+}
+
+#[cfg(not(feature = "ring"))]
+use self::rsa_key_pair as ec_key_pair;
+
+#[cfg(not(feature = "ring"))]
+fn sign_rs256(_der: &[u8], _message: &[u8]) -> Result<Vec<u8>, Error> {
+    Err(invalid_key("local keys require the `ring` feature"))
+}
+
+#[cfg(not(feature = "ring"))]
+use self::sign_rs256 as sign_es256;
+
+/// The modulus and exponent of the RSA key in a PEM encoded X.509
+/// certificate.
+pub(crate) fn rsa_public_key_from_cert(pem: &str) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let (_, body) = pem_body(pem)?;
+    let der =
+        base64::decode(&*body).map_err(|err| invalid_key(format!("invalid base64: {}", err)))?;
+    let (certificate, _) = read_tlv(&der, TAG_SEQUENCE)?;
+    let (mut tbs, _) = read_tlv(certificate, TAG_SEQUENCE)?;
+    if tbs.first() == Some(&TAG_EXPLICIT_VERSION) {
+        tbs = read_tlv(tbs, TAG_EXPLICIT_VERSION)?.1;
+    }
+    // Skip the serial number, signature algorithm, issuer, validity and subject.
+    tbs = read_tlv(tbs, TAG_INTEGER)?.1;
+    for _ in 0..4 {
+        tbs = read_tlv(tbs, TAG_SEQUENCE)?.1;
+    }
+    let (public_key_info, _) = read_tlv(tbs, TAG_SEQUENCE)?;
+    let (algorithm, rest) = read_tlv(public_key_info, TAG_SEQUENCE)?;
+    if read_tlv(algorithm, TAG_OID)?.0 != RSA_ENCRYPTION_OID {
+        return Err(invalid_key("certificate key is not an RSA key"));
+    }
+    let (bits, _) = read_tlv(rest, TAG_BIT_STRING)?;
+    let (public_key, _) = read_tlv(bits.get(1..).unwrap_or_default(), TAG_SEQUENCE)?;
+    let (modulus, rest) = read_tlv(public_key, TAG_INTEGER)?;
+    let (exponent, _) = read_tlv(rest, TAG_INTEGER)?;
+    Ok((modulus.to_vec(), exponent.to_vec()))
+}
+
+fn pem_body(pem: &str) -> Result<(&str, SecretBytes), Error> {
+    let mut lines = pem
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| line.is_empty());
+    let label = lines
+        .next()
+        .and_then(|line| line.strip_prefix("-----BEGIN "))
+        .and_then(|line| line.strip_suffix("-----"))
+        .ok_or_else(|| invalid_key("missing PEM header"))?;
+    let end = format!("-----END {}-----", label);
+
+    let mut body = Vec::new();
+    for line in lines {
+        if line == end {
+            return Ok((label, SecretBytes::new(body)));
+        }
+        body.extend_from_slice(line.as_bytes());
+    }
+    Err(invalid_key("missing PEM footer"))
+}
+
+fn read_tlv(der: &[u8], tag: u8) -> Result<(&[u8], &[u8]), Error> {
+    let malformed = || invalid_key("malformed DER");
+    match der.first() {
+        Some(&found) if found == tag => {}
+        _ => return Err(malformed()),
+    }
+    let first = *der.get(1).ok_or_else(malformed)?;
+    let (len, header) = if first < 0x80 {
+        (first as usize, 2)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return Err(malformed());
+        }
+        let bytes = der.get(2..2 + count).ok_or_else(malformed)?;
+        let len = bytes
+            .iter()
+            .fold(0usize, |len, byte| (len << 8) | *byte as usize);
+        (len, 2 + count)
+    };
+    let end = header.checked_add(len).ok_or_else(malformed)?;
+    let value = der.get(header..end).ok_or_else(malformed)?;
+    Ok((value, &der[end..]))
+}
+
+fn invalid_key(reason: impl Into<String>) -> Error {
+    Error::InvalidKey(reason.into())
+}
+
+#[cfg(all(test, feature = "ring"))]
+mod tests {
+    use super::*;
+    use ring::signature::{primitive, RSA_PKCS1_2048_8192_SHA256};
+
+    const RSA_PKCS1: &str = include_str!("../testdata/rsa_pkcs1.pem");
+    const RSA_PKCS8: &str = include_str!("../testdata/rsa_pkcs8.pem");
+    const EC_PKCS8: &str = include_str!("../testdata/ec_pkcs8.pem");
+    const RSA_CERT: &str = include_str!("../testdata/rsa_cert.pem");
+
+    fn der(pem: &str) -> SecretBytes {
+        SecretBytes::new(base64::decode(&*pem_body(pem).unwrap().1).unwrap())
+    }
+
+    fn assert_malformed<T: std::fmt::Debug>(result: Result<T, Error>) {
+        match result {
+            Err(Error::InvalidKey(reason)) => assert_eq!(reason, "malformed DER"),
+            other => panic!("expected malformed DER, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_rsa_and_ec_keys() {
+        for pem in &[RSA_PKCS1, RSA_PKCS8] {
+            let key = PrivateKey::from_pem(pem).unwrap();
+            assert!(matches!(key, PrivateKey::Rsa(_)));
+            assert_eq!(key.algorithm(), "RS256");
+            assert!(matches!(
+                PrivateKey::from_der(der(pem)),
+                Ok(PrivateKey::Rsa(_))
+            ));
+        }
+        let key = PrivateKey::from_pem(EC_PKCS8).unwrap();
+        assert!(matches!(key, PrivateKey::Ec(_)));
+        assert_eq!(key.algorithm(), "ES256");
+        assert!(matches!(
+            PrivateKey::from_der(der(EC_PKCS8)),
+            Ok(PrivateKey::Ec(_))
+        ));
+    }
+
+    #[test]
+    fn pkcs8_rsa_key_signs_like_its_pkcs1_form() {
+        let message = b"message";
+        let signature = PrivateKey::from_pem(RSA_PKCS8)
+            .unwrap()
+            .sign_bytes(message)
+            .unwrap();
+        // PKCS#1 v1.5 signatures are deterministic.
+        let pkcs1 = PrivateKey::from_pem(RSA_PKCS1).unwrap();
+        assert_eq!(signature, pkcs1.sign_bytes(message).unwrap());
+
+        let (modulus, exponent) = rsa_public_key_from_cert(RSA_CERT).unwrap();
+        let modulus = modulus.strip_prefix(&[0]).unwrap_or(&modulus);
+        assert_eq!(exponent, [1, 0, 1]);
+        primitive::verify_rsa(
+            &RSA_PKCS1_2048_8192_SHA256,
+            (
+                untrusted::Input::from(modulus),
+                untrusted::Input::from(&exponent),
+            ),
+            untrusted::Input::from(message),
+            untrusted::Input::from(&signature),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn signs_es256_jwts() {
+        let key = PrivateKey::from_pem(EC_PKCS8).unwrap();
+        let jwt = key.sign_jwt(Some("kid"), &serde_json::json!({})).unwrap();
+        let parts: Vec<_> = jwt.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        let header = base64::decode_config(parts[0], base64::URL_SAFE_NO_PAD).unwrap();
+        assert_eq!(header, br#"{"typ":"JWT","alg":"ES256","kid":"kid"}"#);
+        let signature = base64::decode_config(parts[2], base64::URL_SAFE_NO_PAD).unwrap();
+        assert_eq!(signature.len(), 64);
+    }
+
+    #[test]
+    fn rejects_truncated_keys() {
+        for pem in &[RSA_PKCS1, RSA_PKCS8, EC_PKCS8] {
+            let der = der(pem);
+            for len in &[0, 1, 2, 4, 16, der.len() / 2, der.len() - 1] {
+                let truncated = SecretBytes::new(der[..*len].to_vec());
+                assert!(matches!(
+                    PrivateKey::from_der(truncated),
+                    Err(Error::InvalidKey(_))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn reads_short_and_long_form_lengths() {
+        assert_eq!(
+            read_tlv(&[TAG_INTEGER, 1, 7, 9], TAG_INTEGER).unwrap(),
+            (&[7][..], &[9][..])
+        );
+        let mut long = vec![TAG_OCTET_STRING, 0x82, 0x01, 0x00];
+        long.extend_from_slice(&[0; 256]);
+        let (value, rest) = read_tlv(&long, TAG_OCTET_STRING).unwrap();
+        assert_eq!((value.len(), rest.len()), (256, 0));
+    }
+
+    #[test]
+    fn rejects_malformed_lengths() {
+        // Wrong tag, missing length, truncated long-form length.
+        assert_malformed(read_tlv(&[TAG_INTEGER, 0], TAG_SEQUENCE));
+        assert_malformed(read_tlv(&[TAG_SEQUENCE], TAG_SEQUENCE));
+        assert_malformed(read_tlv(&[TAG_SEQUENCE, 0x82, 0x01], TAG_SEQUENCE));
+        // Indefinite and over-long length encodings.
+        assert_malformed(read_tlv(&[TAG_SEQUENCE, 0x80, 0, 0], TAG_SEQUENCE));
+        assert_malformed(read_tlv(
+            &[TAG_SEQUENCE, 0x85, 0, 0, 0, 0, 1, 0],
+            TAG_SEQUENCE,
+        ));
+        // Lengths past the end of the input, up to the largest encodable.
+        assert_malformed(read_tlv(&[TAG_SEQUENCE, 3, 0, 0], TAG_SEQUENCE));
+        assert_malformed(read_tlv(
+            &[TAG_SEQUENCE, 0x84, 0xff, 0xff, 0xff, 0xff, 0],
+            TAG_SEQUENCE,
+        ));
+    }
+
+    #[test]
+    fn rejects_unsupported_pem() {
+        let pem = RSA_PKCS8.replace("PRIVATE KEY", "PUBLIC KEY");
+        assert!(matches!(
+            PrivateKey::from_pem(&pem),
+            Err(Error::InvalidKey(reason)) if reason.contains("unsupported PEM label")
+        ));
+        let pem = RSA_PKCS8.replace("-----END PRIVATE KEY-----", "");
+        assert!(matches!(
+            PrivateKey::from_pem(&pem),
+            Err(Error::InvalidKey(reason)) if reason == "missing PEM footer"
+        ));
+    }
+}
@@ -0,0 +1,475 @@
+use crate::{Error, TokenStorage, UserCredentials};
+use std::fmt;
+
+const DEFAULT_SERVICE: &str = "google-token-provider";
+
+/// A [`TokenStorage`] backed by the OS credential store: the login Keychain
+/// on macOS, the Credential Manager on Windows and the Secret Service, e.g.
+/// GNOME Keyring or KWallet, elsewhere.
+///
+/// macOS and the Secret Service are reached through the `security` and
+/// `secret-tool` commands. Secrets are passed to them on stdin, never as
+/// arguments.
+pub struct KeyringTokenStorage {
+    service: String,
+}
+
+impl KeyringTokenStorage {
+    /// Stores entries under `google-token-provider`.
+    pub fn new() -> Self {
+        Self::with_service(DEFAULT_SERVICE)
+    }
+
+    /// Stores entries under `service`, to keep them apart from other
+    /// applications using this crate.
+    pub fn with_service(service: impl Into<String>) -> Self {
+        KeyringTokenStorage {
+            service: service.into(),
+        }
+    }
+}
+
+impl Default for KeyringTokenStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for KeyringTokenStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KeyringTokenStorage")
+            .field("service", &self.service)
+            .finish()
+    }
+}
+
+impl TokenStorage for KeyringTokenStorage {
+    fn load(&self, key: &str) -> Result<Option<UserCredentials>, Error> {
+        match platform::load(&self.service, key)? {
+            Some(secret) => decode(key, &secret).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn store(&self, key: &str, credentials: &UserCredentials) -> Result<(), Error> {
+        platform::store(&self.service, key, &encode(credentials)?)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        platform::delete(&self.service, key)
+    }
+}
+
+/// Encoded so the secret is a single line without quotes.
+fn encode(credentials: &UserCredentials) -> Result<String, Error> {
+    Ok(base64::encode(&serde_json::to_vec(credentials)?))
+}
+
+fn decode(key: &str, secret: &str) -> Result<UserCredentials, Error> {
+    let json = base64::decode(secret.trim())
+        .ok()
+        .and_then(|json| String::from_utf8(json).ok())
+        .ok_or_else(|| {
+            Error::InvalidCredentials(format!("keyring entry {:?} is corrupted", key))
+        })?;
+    UserCredentials::from_json_str(&json)
+}
+
+#[cfg(not(windows))]
+fn run(
+    program: &str,
+    args: &[impl AsRef<std::ffi::OsStr>],
+    stdin: Option<&str>,
+) -> Result<std::process::Output, Error> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            Error::Io(std::io::Error::new(
+                err.kind(),
+                format!("failed to run {}: {}", program, err),
+            ))
+        })?;
+    if let Some(input) = stdin {
+        child.stdin.take().unwrap().write_all(input.as_bytes())?;
+    }
+    Ok(child.wait_with_output()?)
+}
+
+#[cfg(not(windows))]
+fn failed(program: &str, output: &std::process::Output) -> Error {
+    Error::Io(std::io::Error::other(format!(
+        "{} failed: {}",
+        program,
+        String::from_utf8_lossy(&output.stderr).trim()
+    )))
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::{failed, run};
+    use crate::Error;
+
+    // Returned by `security` for missing items.
+    const NOT_FOUND: i32 = 44;
+
+    pub(super) fn load(service: &str, key: &str) -> Result<Option<String>, Error> {
+        let output = run("security", &find_args(service, key), None)?;
+        match output.status.code() {
+            Some(0) => Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned())),
+            Some(NOT_FOUND) => Ok(None),
+            _ => Err(failed("security", &output)),
+        }
+    }
+
+    pub(super) fn store(service: &str, key: &str, secret: &str) -> Result<(), Error> {
+        // `security -i` reads commands from stdin, keeping the secret out of
+        // the process list.
+        let output = run(
+            "security",
+            &["-i"],
+            Some(&add_command(service, key, secret)?),
+        )?;
+        if output.status.success() && output.stderr.is_empty() {
+            Ok(())
+        } else {
+            Err(failed("security", &output))
+        }
+    }
+
+    pub(super) fn delete(service: &str, key: &str) -> Result<(), Error> {
+        let output = run("security", &delete_args(service, key), None)?;
+        match output.status.code() {
+            Some(0) | Some(NOT_FOUND) => Ok(()),
+            _ => Err(failed("security", &output)),
+        }
+    }
+
+    fn find_args<'a>(service: &'a str, key: &'a str) -> [&'a str; 6] {
+        ["find-generic-password", "-s", service, "-a", key, "-w"]
+    }
+
+    fn add_command(service: &str, key: &str, secret: &str) -> Result<String, Error> {
+        Ok(format!(
+            "add-generic-password -U -s {} -a {} -w {}\n",
+            quote(service)?,
+            quote(key)?,
+            secret
+        ))
+    }
+
+    fn delete_args<'a>(service: &'a str, key: &'a str) -> [&'a str; 5] {
+        ["delete-generic-password", "-s", service, "-a", key]
+    }
+
+    fn quote(value: &str) -> Result<String, Error> {
+        if value.contains(|c| c == '"' || c == '\\' || c == '\n') {
+            return Err(Error::InvalidCredentials(format!(
+                "keychain names cannot contain quotes, backslashes or newlines: {:?}",
+                value
+            )));
+        }
+        Ok(format!("\"{}\"", value))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn builds_commands() {
+            assert_eq!(
+                find_args("service", "key"),
+                ["find-generic-password", "-s", "service", "-a", "key", "-w"]
+            );
+            assert_eq!(
+                add_command("service", "key", "c2VjcmV0").unwrap(),
+                "add-generic-password -U -s \"service\" -a \"key\" -w c2VjcmV0\n"
+            );
+            assert_eq!(
+                delete_args("service", "key"),
+                ["delete-generic-password", "-s", "service", "-a", "key"]
+            );
+        }
+
+        #[test]
+        fn rejects_names_that_break_quoting() {
+            for name in ["a\"b", "a\\b", "a\nb"] {
+                assert!(add_command(name, "key", "secret").is_err(), "{:?}", name);
+                assert!(
+                    add_command("service", name, "secret").is_err(),
+                    "{:?}",
+                    name
+                );
+            }
+        }
+    }
+}
+
+#[cfg(all(not(windows), not(target_os = "macos")))]
+mod platform {
+    use super::{failed, run};
+    use crate::Error;
+
+    pub(super) fn load(service: &str, key: &str) -> Result<Option<String>, Error> {
+        let output = run("secret-tool", &lookup_args(service, key), None)?;
+        match (output.status.success(), output.stdout.is_empty()) {
+            (true, false) => Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned())),
+            // A lookup without a match fails without printing anything.
+            (_, true) if output.stderr.is_empty() => Ok(None),
+            _ => Err(failed("secret-tool", &output)),
+        }
+    }
+
+    pub(super) fn store(service: &str, key: &str, secret: &str) -> Result<(), Error> {
+        let output = run("secret-tool", &store_args(service, key), Some(secret))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(failed("secret-tool", &output))
+        }
+    }
+
+    pub(super) fn delete(service: &str, key: &str) -> Result<(), Error> {
+        let output = run("secret-tool", &clear_args(service, key), None)?;
+        // Clearing a missing entry also exits with an error, but silently.
+        if output.status.success() || output.stderr.is_empty() {
+            Ok(())
+        } else {
+            Err(failed("secret-tool", &output))
+        }
+    }
+
+    fn lookup_args<'a>(service: &'a str, key: &'a str) -> [&'a str; 5] {
+        ["lookup", "service", service, "account", key]
+    }
+
+    /// The secret itself is read from stdin.
+    fn store_args(service: &str, key: &str) -> [String; 7] {
+        [
+            "store".to_owned(),
+            "--label".to_owned(),
+            format!("{} {}", service, key),
+            "service".to_owned(),
+            service.to_owned(),
+            "account".to_owned(),
+            key.to_owned(),
+        ]
+    }
+
+    fn clear_args<'a>(service: &'a str, key: &'a str) -> [&'a str; 5] {
+        ["clear", "service", service, "account", key]
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn builds_commands() {
+            assert_eq!(
+                lookup_args("service", "key"),
+                ["lookup", "service", "service", "account", "key"]
+            );
+            assert_eq!(
+                store_args("service", "key"),
+                [
+                    "store",
+                    "--label",
+                    "service key",
+                    "service",
+                    "service",
+                    "account",
+                    "key"
+                ]
+            );
+            assert_eq!(
+                clear_args("service", "key"),
+                ["clear", "service", "service", "account", "key"]
+            );
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use crate::secret;
+    use crate::Error;
+    use std::ffi::c_void;
+    use std::io;
+    use std::ptr;
+    use std::slice;
+
+    const CRED_TYPE_GENERIC: u32 = 1;
+    const CRED_PERSIST_LOCAL_MACHINE: u32 = 2;
+    const ERROR_NOT_FOUND: i32 = 1168;
+
+    #[repr(C)]
+    struct Credential {
+        flags: u32,
+        kind: u32,
+        target_name: *mut u16,
+        comment: *mut u16,
+        last_written: [u32; 2],
+        credential_blob_size: u32,
+        credential_blob: *mut u8,
+        persist: u32,
+        attribute_count: u32,
+        attributes: *mut c_void,
+        target_alias: *mut u16,
+        user_name: *mut u16,
+    }
+
+    #[link(name = "advapi32")]
+    extern "system" {
+        fn CredReadW(target: *const u16, kind: u32, flags: u32, out: *mut *mut Credential) -> i32;
+        fn CredWriteW(credential: *const Credential, flags: u32) -> i32;
+        fn CredDeleteW(target: *const u16, kind: u32, flags: u32) -> i32;
+        fn CredFree(buffer: *mut c_void);
+    }
+
+    fn wide(value: &str) -> Vec<u16> {
+        value.encode_utf16().chain(Some(0)).collect()
+    }
+
+    fn target(service: &str, key: &str) -> Vec<u16> {
+        wide(&format!("{}:{}", service, key))
+    }
+
+    fn not_found(err: &io::Error) -> bool {
+        err.raw_os_error() == Some(ERROR_NOT_FOUND)
+    }
+
+    pub(super) fn load(service: &str, key: &str) -> Result<Option<String>, Error> {
+        let target = target(service, key);
+        let mut credential = ptr::null_mut();
+        if unsafe { CredReadW(target.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) } == 0 {
+            let err = io::Error::last_os_error();
+            return if not_found(&err) {
+                Ok(None)
+            } else {
+                Err(err.into())
+            };
+        }
+        let secret = unsafe {
+            let (blob, size) = (
+                (*credential).credential_blob,
+                (*credential).credential_blob_size as usize,
+            );
+            let mut value = String::new();
+            if !blob.is_null() {
+                let blob = slice::from_raw_parts_mut(blob, size);
+                value = String::from_utf8_lossy(blob).into_owned();
+                secret::wipe(blob);
+            }
+            CredFree(credential as *mut c_void);
+            value
+        };
+        Ok(Some(secret))
+    }
+
+    pub(super) fn store(service: &str, key: &str, value: &str) -> Result<(), Error> {
+        let mut target = target(service, key);
+        let mut user_name = wide(key);
+        let credential = Credential {
+            flags: 0,
+            kind: CRED_TYPE_GENERIC,
+            target_name: target.as_mut_ptr(),
+            comment: ptr::null_mut(),
+            last_written: [0; 2],
+            credential_blob_size: value.len() as u32,
+            credential_blob: value.as_ptr() as *mut u8,
+            persist: CRED_PERSIST_LOCAL_MACHINE,
+            attribute_count: 0,
+            attributes: ptr::null_mut(),
+            target_alias: ptr::null_mut(),
+            user_name: user_name.as_mut_ptr(),
+        };
+        match unsafe { CredWriteW(&credential, 0) } {
+            0 => Err(io::Error::last_os_error().into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub(super) fn delete(service: &str, key: &str) -> Result<(), Error> {
+        let target = target(service, key);
+        if unsafe { CredDeleteW(target.as_ptr(), CRED_TYPE_GENERIC, 0) } == 0 {
+            let err = io::Error::last_os_error();
+            if !not_found(&err) {
+                return Err(err.into());
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn builds_targets() {
+            let target = target("service", "key");
+            assert_eq!(String::from_utf16(&target).unwrap(), "service:key\0");
+        }
+    }
+}
+
+// The round trips below need a real, unlocked credential store, which CI
+// machines rarely have, so they are `#[ignore]`d. Run them with
+// `cargo test --features keyring -- --ignored`.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials() -> UserCredentials {
+        UserCredentials::new("client-id", "client-secret", "refresh-token")
+    }
+
+    #[test]
+    fn encodes_entries_on_one_line() {
+        let secret = encode(&credentials()).unwrap();
+        assert!(!secret.contains(['\n', '"', '\'']));
+
+        // Commands may print the secret with a trailing newline.
+        let decoded = decode("key", &format!("{}\n", secret)).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(credentials()).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_corrupted_entries() {
+        for secret in [
+            "not base64!",
+            &base64::encode(b"\xff"),
+            &base64::encode(b"{}"),
+        ] {
+            assert!(decode("key", secret).is_err(), "{:?}", secret);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs the OS credential store"]
+    fn round_trips_through_the_credential_store() {
+        let storage = KeyringTokenStorage::with_service("google-token-provider-test");
+        let key = format!("round-trip-{}", std::process::id());
+        storage.store(&key, &credentials()).unwrap();
+
+        let loaded = storage.load(&key).unwrap().unwrap();
+        assert_eq!(
+            serde_json::to_value(&loaded).unwrap(),
+            serde_json::to_value(credentials()).unwrap()
+        );
+        storage.delete(&key).unwrap();
+        assert!(storage.load(&key).unwrap().is_none());
+        // Deleting a missing entry is not an error.
+        storage.delete(&key).unwrap();
+    }
+}
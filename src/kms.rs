@@ -0,0 +1,127 @@
+use crate::secret::SecretBytes;
+use crate::{default_headers, default_http_client, parse_response, Error, Signer, TokenProvider};
+use reqwest::Client as HTTPClient;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+fn kms_url(universe_domain: &str) -> String {
+    format!("https://cloudkms.{}/v1", universe_domain)
+}
+
+#[derive(Serialize, Debug)]
+struct AsymmetricSignRequest {
+    digest: Digest,
+}
+
+#[derive(Serialize, Debug)]
+struct Digest {
+    sha256: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct AsymmetricSignResponse {
+    signature: String,
+}
+
+#[derive(Serialize, Debug)]
+struct DecryptRequest {
+    ciphertext: String,
+}
+
+#[derive(Deserialize)]
+struct DecryptResponse {
+    plaintext: String,
+}
+
+/// A Cloud KMS asymmetric signing key version, used in place of a local
+/// private key. The key must use an `RSA_SIGN_PKCS1_*_SHA256` algorithm so
+/// that its signatures are valid RS256.
+pub struct KmsKey {
+    name: String,
+    provider: Arc<dyn TokenProvider>,
+    http: HTTPClient,
+}
+
+impl KmsKey {
+    /// `name` is the full resource name of the key version, e.g.
+    /// `projects/p/locations/l/keyRings/r/cryptoKeys/k/cryptoKeyVersions/1`.
+    /// `provider` authorizes the calls to Cloud KMS.
+    pub fn new(name: impl Into<String>, provider: Arc<dyn TokenProvider>) -> Self {
+        KmsKey {
+            name: name.into(),
+            provider,
+            http: default_http_client(),
+        }
+    }
+
+    pub fn http_client(mut self, http: HTTPClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Signer for KmsKey {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let token = self.provider.get_token()?;
+        let request = AsymmetricSignRequest {
+            digest: Digest {
+                sha256: base64::encode(digest(&SHA256, message).as_ref()),
+            },
+        };
+        let response = self
+            .http
+            .post(&format!(
+                "{}/{}:asymmetricSign",
+                kms_url(self.provider.universe_domain()),
+                self.name
+            ))
+            .headers(default_headers())
+            .bearer_auth(&token.value)
+            .json(&request)
+            .send()?;
+        let response = parse_response::<AsymmetricSignResponse>(response)?;
+        base64::decode(&response.signature)
+            .map_err(|err| Error::InvalidKey(format!("invalid KMS signature: {}", err)))
+    }
+}
+
+impl fmt::Debug for KmsKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KmsKey").field("name", &self.name).finish()
+    }
+}
+
+/// Decrypts `ciphertext` with the symmetric key `name`, e.g.
+/// `projects/p/locations/l/keyRings/r/cryptoKeys/k`.
+pub(crate) fn decrypt(
+    name: &str,
+    provider: &dyn TokenProvider,
+    ciphertext: &[u8],
+) -> Result<SecretBytes, Error> {
+    let token = provider.get_token()?;
+    let request = DecryptRequest {
+        ciphertext: base64::encode(ciphertext),
+    };
+    let response = default_http_client()
+        .post(&format!(
+            "{}/{}:decrypt",
+            kms_url(provider.universe_domain()),
+            name
+        ))
+        .headers(default_headers())
+        .bearer_auth(&token.value)
+        .json(&request)
+        .send()?;
+    let mut response = parse_response::<DecryptResponse>(response)?;
+    let plaintext = base64::decode(&response.plaintext)
+        .map(SecretBytes::new)
+        .map_err(|err| Error::InvalidKey(format!("invalid KMS plaintext: {}", err)));
+    crate::secret::wipe_str(&mut response.plaintext);
+    plaintext
+}
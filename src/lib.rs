@@ -1,30 +1,224 @@
-use failure::Error;
-use jsonwebtoken::{Algorithm, Header};
-use openssl::pkey::Private;
-use openssl::rsa::Rsa;
-use reqwest::Client as HTTPClient;
+// Much of the shared core is only used by the blocking clients.
+#![cfg_attr(not(feature = "blocking"), allow(dead_code, unused_imports))]
+
+use http::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, RETRY_AFTER, USER_AGENT};
+#[cfg(feature = "blocking")]
 use reqwest::Response;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cmp;
+use std::fs;
 use std::ops::Add;
 use std::time::UNIX_EPOCH;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
-const TOKEN_URL: &str = "https://www.googleapis.com/oauth2/v4/token";
+#[macro_use]
+mod logging;
 
-#[derive(Debug, Clone)]
-pub struct Credentials {
-    private_key: Rsa<Private>,
-    client_email: String,
-}
+#[cfg(feature = "async")]
+mod async_client;
+mod audit;
+#[cfg(feature = "blocking")]
+mod authorized;
+#[cfg(feature = "blocking")]
+mod aws;
+mod cache;
+#[cfg(feature = "blocking")]
+mod circuit;
+#[cfg(feature = "blocking")]
+mod client;
+mod clock;
+mod credentials;
+#[cfg(feature = "blocking")]
+mod device;
+#[cfg(feature = "blocking")]
+mod downscoped;
+mod encoding;
+#[cfg(feature = "ring")]
+mod encrypted;
+mod error;
+#[cfg(feature = "blocking")]
+mod external_account;
+#[cfg(feature = "blocking")]
+mod file_cache;
+mod firebase;
+#[cfg(feature = "hyper")]
+mod hyper_client;
+#[cfg(feature = "blocking")]
+mod iam;
+#[cfg(feature = "blocking")]
+mod impersonated;
+#[cfg(feature = "blocking")]
+mod installed;
+mod key;
+#[cfg(feature = "keyring")]
+mod keyring;
+#[cfg(feature = "blocking")]
+mod kms;
+#[cfg(feature = "blocking")]
+mod manager;
+#[cfg(feature = "blocking")]
+mod metadata;
+#[cfg(feature = "test-utils")]
+mod mock_server;
+#[cfg(all(feature = "blocking", feature = "default-tls"))]
+mod mtls;
+mod observer;
+#[cfg(feature = "blocking")]
+mod proxy;
+#[cfg(feature = "blocking")]
+mod push;
+#[cfg(feature = "blocking")]
+mod refresher;
+#[cfg(feature = "blocking")]
+mod retry;
+#[cfg(feature = "blocking")]
+mod revoke;
+#[cfg(feature = "blocking")]
+mod rotation;
+mod scope;
+mod secret;
+#[cfg(feature = "blocking")]
+mod secret_manager;
+mod signer;
+#[cfg(feature = "ring")]
+mod storage;
+#[cfg(feature = "blocking")]
+mod sts;
+#[cfg(feature = "blocking")]
+mod subject_token;
+mod testing;
+#[cfg(feature = "blocking")]
+mod token_info;
+#[cfg(feature = "blocking")]
+mod token_storage;
+mod transport;
+#[cfg(feature = "blocking")]
+mod user;
+#[cfg(feature = "vault")]
+mod vault;
+#[cfg(feature = "blocking")]
+mod verify;
+
+#[cfg(feature = "async")]
+pub use async_client::AsyncClient;
+#[cfg(feature = "ring")]
+pub use audit::{verify_audit_log, ChainedAuditLog};
+pub use audit::{AuditEntry, AuditLog, AuditOutcome};
+#[cfg(feature = "blocking")]
+pub use authorized::AuthorizedClient;
+pub use cache::{MemoryTokenCache, TokenCache};
+#[cfg(feature = "blocking")]
+pub use circuit::{CircuitBreaker, CircuitState};
+#[cfg(feature = "blocking")]
+pub use client::{Client, ClientBuilder};
+pub use clock::{Clock, MockClock, SystemClock};
+pub use credentials::Credentials;
+#[cfg(feature = "blocking")]
+pub use device::{DeviceCode, DeviceFlow};
+#[cfg(feature = "blocking")]
+pub use downscoped::{AccessBoundaryRule, CredentialAccessBoundary, DownscopedClient};
+#[cfg(feature = "ring")]
+pub use encrypted::encrypt_key_file;
+pub use error::Error;
+#[cfg(feature = "blocking")]
+pub use external_account::ExternalAccountClient;
+#[cfg(feature = "blocking")]
+pub use file_cache::FileTokenCache;
+pub use firebase::{create_custom_token, create_tenant_custom_token};
+#[cfg(feature = "hyper")]
+pub use hyper_client::HyperClient;
+#[cfg(feature = "blocking")]
+pub use iam::IamSigner;
+#[cfg(feature = "blocking")]
+pub use impersonated::ImpersonatedClient;
+#[cfg(feature = "blocking")]
+pub use installed::InstalledFlow;
+#[cfg(feature = "keyring")]
+pub use keyring::KeyringTokenStorage;
+#[cfg(feature = "blocking")]
+pub use kms::KmsKey;
+#[cfg(feature = "blocking")]
+pub use manager::TokenManager;
+#[cfg(feature = "blocking")]
+pub use metadata::MetadataClient;
+#[cfg(feature = "test-utils")]
+pub use mock_server::{MockResponse, MockTokenServer, RecordedRequest};
+#[cfg(all(feature = "blocking", feature = "default-tls"))]
+pub use mtls::{use_client_certificate, ClientCertificate, MTLS_TOKEN_URL};
+pub use observer::TokenObserver;
+#[cfg(feature = "blocking")]
+pub use push::{OidcToken, PushVerifier};
+#[cfg(feature = "blocking")]
+pub use refresher::{BackgroundRefresher, TokenSubscription};
+#[cfg(feature = "blocking")]
+pub use retry::RetryPolicy;
+#[cfg(feature = "blocking")]
+pub use revoke::revoke_token;
+#[cfg(feature = "blocking")]
+pub use rotation::KeyRotationClient;
+pub use scope::Scope;
+#[cfg(feature = "blocking")]
+pub use secret_manager::{SecretManagerClient, SecretManagerCredentials};
+pub use signer::Signer;
+#[cfg(feature = "ring")]
+pub use storage::{sign_url, PostPolicy, SignedPostPolicy, SignedUrl};
+#[cfg(feature = "blocking")]
+pub use sts::{StsClient, TokenExchangeRequest, TokenExchangeResponse};
+#[cfg(feature = "blocking")]
+pub use subject_token::{
+    FileTokenSupplier, SubjectTokenFormat, SubjectTokenSupplier, UrlTokenSupplier,
+};
+pub use testing::{FakeTokenProvider, StaticTokenProvider};
+#[cfg(feature = "blocking")]
+pub use token_info::{token_info, TokenInfo};
+#[cfg(feature = "blocking")]
+pub use token_storage::{FileTokenStorage, TokenStorage};
+pub use transport::{HttpResponse, HttpTransport, TransportClient};
+#[cfg(feature = "blocking")]
+pub use user::{UserClient, UserCredentials};
+#[cfg(feature = "vault")]
+pub use vault::{VaultClient, VaultKeyClient};
+#[cfg(feature = "blocking")]
+pub use verify::{
+    FirebaseClaims, FirebaseInfo, FirebaseTokenVerifier, IdTokenVerifier, VerifiedClaims,
+    IAP_JWT_HEADER,
+};
+
+const TOKEN_URL: &str = "https://www.googleapis.com/oauth2/v4/token";
+/// The universe of Google's public cloud, as opposed to Trusted Partner
+/// Cloud and other sovereign universes.
+pub const DEFAULT_UNIVERSE_DOMAIN: &str = "googleapis.com";
+const GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+const QUOTA_PROJECT_HEADER: &str = "x-goog-user-project";
+const API_CLIENT_HEADER: &str = "x-goog-api-client";
+const API_CLIENT: &str = concat!(
+    "gl-rust/",
+    env!("RUSTC_VERSION"),
+    " auth/",
+    env!("CARGO_PKG_VERSION")
+);
+/// Google rejects assertions that are valid for longer than an hour.
+const MAX_ASSERTION_LIFETIME: Duration = Duration::from_secs(60 * 60);
+/// How far `iat` is backdated to tolerate clocks that run slightly fast.
+const DEFAULT_CLOCK_SKEW: Duration = Duration::from_secs(10);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_USER_AGENT: &str = concat!("google-token-provider/", env!("CARGO_PKG_VERSION"));
 
 #[derive(Default, Serialize, Deserialize, PartialEq, Debug)]
 struct Claims {
     iss: String,
-    scope: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_audience: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sub: Option<String>,
     aud: String,
     exp: u64,
     iat: u64,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Default, Deserialize, PartialEq, Debug, Clone)]
@@ -32,94 +226,438 @@ struct TokenResponse {
     access_token: String,
     token_type: String,
     expires_in: u64,
+    scope: Option<String>,
+    id_token: Option<String>,
 }
 
-impl Credentials {
-    pub fn new(private_key: Rsa<Private>, client_email: String) -> Self {
-        Credentials {
-            private_key,
-            client_email,
+#[derive(Deserialize, Debug)]
+struct OAuthErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct IdTokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct IdTokenClaims {
+    exp: u64,
+}
+
+impl From<OAuthErrorResponse> for Error {
+    fn from(response: OAuthErrorResponse) -> Self {
+        if let Some(description) = &response.error_description {
+            let skewed = description.contains("Token used too early")
+                || description.contains("reasonable timeframe");
+            if response.error == "invalid_grant" && skewed {
+                return Error::ClockSkew(description.clone());
+            }
         }
+        Error::OAuth {
+            error: response.error,
+            error_description: response.error_description,
+        }
+    }
+}
+
+impl From<TokenResponse> for AccessToken {
+    fn from(response: TokenResponse) -> Self {
+        AccessToken::from_response(response, SystemTime::now())
     }
 }
 
+pub trait TokenProvider: Send + Sync {
+    fn get_token(&self) -> Result<AccessToken, Error>;
+
+    fn invalidate(&self) {}
+
+    /// A new token, bypassing any cached one.
+    fn force_refresh(&self) -> Result<AccessToken, Error> {
+        self.invalidate();
+        self.get_token()
+    }
+
+    fn quota_project_id(&self) -> Option<&str> {
+        None
+    }
+
+    /// The domain of the universe the tokens are valid in, for building
+    /// service endpoints such as `https://storage.{universe_domain}`.
+    fn universe_domain(&self) -> &str {
+        DEFAULT_UNIVERSE_DOMAIN
+    }
+
+    /// The `Authorization` header, plus `x-goog-user-project` when a quota
+    /// project is configured.
+    fn headers(&self) -> Result<HeaderMap, Error> {
+        let token = self.get_token()?;
+        let mut headers = HeaderMap::new();
+        let mut authorization = header_value(&token.bearer())?;
+        authorization.set_sensitive(true);
+        headers.insert(AUTHORIZATION, authorization);
+        if let Some(project) = self.quota_project_id() {
+            headers.insert(
+                HeaderName::from_static(QUOTA_PROJECT_HEADER),
+                header_value(project)?,
+            );
+        }
+        Ok(headers)
+    }
+
+    /// Inserts the headers from [`TokenProvider::headers`] into an outgoing
+    /// request, replacing any existing values.
+    fn authorize(&self, headers: &mut HeaderMap) -> Result<(), Error> {
+        for (name, value) in self.headers()? {
+            if let Some(name) = name {
+                headers.insert(name, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "blocking")]
+/// Falls back to the proxy environment variables when `proxy` is `None`.
+pub(crate) fn http_client(
+    connect_timeout: Duration,
+    timeout: Duration,
+    proxy: Option<reqwest::Proxy>,
+) -> Result<reqwest::Client, Error> {
+    Ok(http_client_builder(connect_timeout, timeout, proxy).build()?)
+}
+
+#[cfg(feature = "blocking")]
+pub(crate) fn http_client_builder(
+    connect_timeout: Duration,
+    timeout: Duration,
+    proxy: Option<reqwest::Proxy>,
+) -> reqwest::ClientBuilder {
+    let builder = reqwest::Client::builder()
+        .connect_timeout(connect_timeout)
+        .timeout(timeout);
+    match proxy.or_else(proxy::from_env) {
+        Some(proxy) => builder.proxy(proxy),
+        None => builder,
+    }
+}
+
+#[cfg(feature = "blocking")]
+/// An HTTP client with the default token request timeouts.
+pub(crate) fn default_http_client() -> reqwest::Client {
+    http_client(DEFAULT_TIMEOUT, DEFAULT_TIMEOUT, None).expect("failed to build HTTP client")
+}
+
+/// Telemetry headers sent with every token request.
+pub(crate) fn default_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        HeaderName::from_static(API_CLIENT_HEADER),
+        HeaderValue::from_static(API_CLIENT),
+    );
+    headers.insert(USER_AGENT, HeaderValue::from_static(DEFAULT_USER_AGENT));
+    headers
+}
+
+/// The default User-Agent followed by a caller supplied product token.
+pub(crate) fn user_agent(product: &str) -> Result<HeaderValue, Error> {
+    header_value(&format!("{} {}", DEFAULT_USER_AGENT, product))
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, Error> {
+    HeaderValue::from_str(value)
+        .map_err(|_| Error::InvalidCredentials(format!("{:?} is not a valid header value", value)))
+}
+
+#[cfg(feature = "blocking")]
+pub fn application_default(
+    scopes: impl IntoIterator<Item = impl AsRef<str>>,
+) -> Result<Box<dyn TokenProvider>, Error> {
+    if let Some(path) = credentials::application_default_file() {
+        let json = fs::read_to_string(path)?;
+        return provider_from_json(&json, scopes);
+    }
+
+    if let Some(path) = credentials::gcloud_credentials_file() {
+        let json = fs::read_to_string(path)?;
+        return provider_from_json(&json, scopes);
+    }
+
+    if MetadataClient::is_available() {
+        return Ok(Box::new(MetadataClient::new(scopes)));
+    }
+
+    Err(Error::CredentialsNotFound)
+}
+
+/// An ID token for `audience` from the same credentials as
+/// [`application_default`]. User credentials can only get ID tokens for
+/// their own OAuth client, and only when granted the `openid` scope, so
+/// `audience` does not apply to them.
+#[cfg(feature = "blocking")]
+pub fn application_default_id_token(audience: &str) -> Result<AccessToken, Error> {
+    let path =
+        credentials::application_default_file().or_else(credentials::gcloud_credentials_file);
+    let json = match path {
+        Some(path) => fs::read_to_string(path)?,
+        None if MetadataClient::is_available() => {
+            return MetadataClient::new(std::iter::empty::<&str>()).get_id_token(audience)
+        }
+        None => return Err(Error::CredentialsNotFound),
+    };
+    match credentials::credentials_type(&json)?.as_str() {
+        "authorized_user" => {
+            let credentials = UserCredentials::from_json_str(&json)?;
+            let token = UserClient::new(credentials).get_token()?;
+            let id_token = token.id_token().ok_or_else(|| {
+                Error::InvalidCredentials(
+                    "user credentials were not granted the openid scope".to_owned(),
+                )
+            })?;
+            AccessToken::from_id_token(id_token.to_owned())
+        }
+        "external_account" => Err(Error::InvalidCredentials(
+            "external account credentials cannot get ID tokens".to_owned(),
+        )),
+        _ => {
+            let credentials = Credentials::from_json_str(&json)?;
+            Client::new(credentials, std::iter::empty::<&str>()).get_id_token(audience)
+        }
+    }
+}
+
+fn collect_scopes(scopes: impl IntoIterator<Item = impl AsRef<str>>) -> Vec<String> {
+    scopes
+        .into_iter()
+        .map(|scope| scope.as_ref().to_owned())
+        .collect()
+}
+
+#[cfg(feature = "blocking")]
+fn provider_from_json(
+    json: &str,
+    scopes: impl IntoIterator<Item = impl AsRef<str>>,
+) -> Result<Box<dyn TokenProvider>, Error> {
+    let provider: Box<dyn TokenProvider> = match credentials::credentials_type(json)?.as_str() {
+        "authorized_user" => {
+            let credentials = UserCredentials::from_json_str(json)?;
+            Box::new(UserClient::new(credentials))
+        }
+        "external_account" => Box::new(ExternalAccountClient::from_json_str(json, scopes)?),
+        _ => {
+            let credentials = Credentials::from_json_str(json)?;
+            Box::new(Client::new(credentials, scopes))
+        }
+    };
+    Ok(provider)
+}
+
 #[derive(Debug, Clone)]
 pub struct AccessToken {
     pub value: String,
+    /// Wall-clock expiry, for display. Expiry checks use a monotonic deadline
+    /// so they are unaffected by system clock adjustments.
     pub expires: SystemTime,
+    deadline: Instant,
+    token_type: Option<String>,
+    scope: Option<String>,
+    id_token: Option<String>,
 }
 
 impl AccessToken {
+    pub fn new(value: impl Into<String>, expires: SystemTime) -> Self {
+        let remaining = expires
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        AccessToken {
+            value: value.into(),
+            expires,
+            deadline: Instant::now() + remaining,
+            token_type: None,
+            scope: None,
+            id_token: None,
+        }
+    }
+
     pub fn expired(&self) -> bool {
-        self.expires < SystemTime::now()
+        Instant::now() >= self.deadline
+    }
+
+    pub fn expired_at(&self, now: SystemTime) -> bool {
+        self.expires < now
+    }
+
+    pub fn expires_within(&self, margin: Duration) -> bool {
+        Instant::now() + margin >= self.deadline
+    }
+
+    pub fn expires_within_at(&self, margin: Duration, now: SystemTime) -> bool {
+        self.expires < now + margin
+    }
+
+    /// Time left until the token expires, measured on the monotonic clock.
+    pub fn expires_in(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    pub(crate) fn from_response(response: TokenResponse, now: SystemTime) -> Self {
+        let expires_in = Duration::from_secs(response.expires_in);
+        AccessToken {
+            value: response.access_token,
+            expires: now + expires_in,
+            deadline: Instant::now() + expires_in,
+            token_type: Some(response.token_type),
+            scope: response.scope,
+            id_token: response.id_token,
+        }
+    }
+
+    /// The `token_type` returned by the token endpoint, usually `Bearer`.
+    pub fn token_type(&self) -> Option<&str> {
+        self.token_type.as_deref()
+    }
+
+    /// The space separated scopes actually granted, when the endpoint reports them.
+    pub fn scope(&self) -> Option<&str> {
+        self.scope.as_deref()
+    }
+
+    pub fn id_token(&self) -> Option<&str> {
+        self.id_token.as_deref()
+    }
+
+    pub fn bearer(&self) -> String {
+        format!("Bearer {}", self.value)
+    }
+
+    /// The `Authorization` header value, marked sensitive so it is not logged.
+    #[cfg(feature = "http")]
+    pub fn header_value(&self) -> Result<http::HeaderValue, http::header::InvalidHeaderValue> {
+        let mut value = http::HeaderValue::from_str(&self.bearer())?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+
+    #[cfg(feature = "blocking")]
+    pub(crate) fn from_id_token(value: String) -> Result<Self, Error> {
+        let claims = jsonwebtoken::dangerous_unsafe_decode::<IdTokenClaims>(&value)?.claims;
+        Ok(AccessToken::new(
+            value,
+            UNIX_EPOCH + Duration::from_secs(claims.exp),
+        ))
     }
 }
 
-pub struct Client {
-    credentials: Credentials,
-    scopes: String,
-    http: HTTPClient,
-    access_token: Option<AccessToken>,
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SerializedToken<'a> {
+    value: std::borrow::Cow<'a, str>,
+    expires: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_type: Option<std::borrow::Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<std::borrow::Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id_token: Option<std::borrow::Cow<'a, str>>,
 }
 
-impl Client {
-    pub fn new<'a>(credentials: Credentials, scopes: impl Iterator<Item = &'a str>) -> Client {
-        Client {
-            credentials,
-            scopes: scopes.collect::<Vec<&str>>().join(" "),
-            http: HTTPClient::new(),
-            access_token: None,
+/// Serialized as the token value and its expiry in seconds since the epoch.
+#[cfg(feature = "serde")]
+impl Serialize for AccessToken {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let expires = self
+            .expires
+            .duration_since(UNIX_EPOCH)
+            .map_err(serde::ser::Error::custom)?;
+        SerializedToken {
+            value: self.value.as_str().into(),
+            expires: expires.as_secs(),
+            token_type: self.token_type.as_deref().map(Into::into),
+            scope: self.scope.as_deref().map(Into::into),
+            id_token: self.id_token.as_deref().map(Into::into),
         }
+        .serialize(serializer)
     }
+}
 
-    pub fn get_token(&mut self) -> Result<AccessToken, Error> {
-        if let Some(token) = &self.access_token {
-            if !token.expired() {
-                return Ok(token.clone());
-            }
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for AccessToken {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let token = SerializedToken::deserialize(deserializer)?;
+        let expires = UNIX_EPOCH + Duration::from_secs(token.expires);
+        let mut access_token = AccessToken::new(token.value, expires);
+        access_token.token_type = token.token_type.map(Into::into);
+        access_token.scope = token.scope.map(Into::into);
+        access_token.id_token = token.id_token.map(Into::into);
+        Ok(access_token)
+    }
+}
+
+impl Drop for AccessToken {
+    fn drop(&mut self) {
+        secret::wipe_str(&mut self.value);
+        if let Some(id_token) = &mut self.id_token {
+            secret::wipe_str(id_token);
         }
+    }
+}
+
+#[cfg(feature = "blocking")]
+pub(crate) fn parse_response<T: DeserializeOwned>(mut response: Response) -> Result<T, Error> {
+    let status = response.status();
+    if !status.is_success() {
+        let retry_after = retry_after(response.headers());
+        return Err(match response.json::<OAuthErrorResponse>() {
+            Ok(response) => Error::from(response),
+            Err(_) => Error::Status {
+                status,
+                retry_after,
+            },
+        });
+    }
+
+    Ok(response.json::<T>()?)
+}
 
-        self.access_token = Some(self.fetch_token()?);
-        Ok(self.access_token.clone().unwrap())
-    }
-
-    fn fetch_token(&mut self) -> Result<AccessToken, Error> {
-        let token = self.create_jwt()?;
-        let mut params = HashMap::new();
-        params.insert("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer");
-        params.insert("assertion", &token);
-
-        self.http
-            .post(TOKEN_URL)
-            .form(&params)
-            .send()
-            .map_err(Error::from)
-            .and_then(Self::parse_response)
-    }
-
-    fn parse_response(mut response: Response) -> Result<AccessToken, Error> {
-        response
-            .json::<TokenResponse>()
-            .map_err(Error::from)
-            .map(|response| AccessToken {
-                value: response.access_token,
-                expires: SystemTime::now() + Duration::from_secs(response.expires_in),
-            })
-    }
-
-    fn create_jwt(&self) -> Result<String, Error> {
-        let header = Header::new(Algorithm::RS256);
-        let iat = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let exp = iat.add(Duration::from_secs(60 * 60));
-        let claims = Claims {
-            iss: self.credentials.client_email.clone(),
-            scope: self.scopes.clone(),
-            aud: TOKEN_URL.to_owned(),
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+impl Claims {
+    fn new(credentials: &Credentials, aud: &str) -> Result<Self, Error> {
+        Self::with_lifetime(
+            credentials,
+            aud,
+            MAX_ASSERTION_LIFETIME,
+            SystemTime::now() - DEFAULT_CLOCK_SKEW,
+        )
+    }
+
+    fn with_lifetime(
+        credentials: &Credentials,
+        aud: &str,
+        lifetime: Duration,
+        issued_at: SystemTime,
+    ) -> Result<Self, Error> {
+        let iat = issued_at.duration_since(UNIX_EPOCH)?;
+        let exp = iat.add(cmp::min(lifetime, MAX_ASSERTION_LIFETIME));
+        Ok(Claims {
+            iss: credentials.client_email.clone(),
+            aud: aud.to_owned(),
             exp: exp.as_secs(),
             iat: iat.as_secs(),
-        };
-        let key = self.credentials.private_key.private_key_to_der()?;
-        let token = jsonwebtoken::encode(&header, &claims, &key)?;
-        Ok(token)
+            ..Claims::default()
+        })
     }
 }
+
+fn create_jwt(credentials: &Credentials, claims: &Claims) -> Result<String, Error> {
+    credentials.sign_jwt(claims)
+}
@@ -0,0 +1,155 @@
+use crate::{AccessToken, Error, TokenProvider};
+use reqwest::header::HeaderMap;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::{Arc, RwLock};
+
+/// A registry of token providers keyed by the caller, e.g. one per tenant,
+/// so each set of credentials keeps its own cache and refresh state.
+pub struct TokenManager<K> {
+    providers: RwLock<HashMap<K, Arc<dyn TokenProvider>>>,
+}
+
+impl<K: Eq + Hash + Debug> TokenManager<K> {
+    pub fn new() -> Self {
+        TokenManager {
+            providers: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Registers `provider` under `key`, returning the one it replaces.
+    pub fn insert(
+        &self,
+        key: K,
+        provider: Arc<dyn TokenProvider>,
+    ) -> Option<Arc<dyn TokenProvider>> {
+        self.providers.write().unwrap().insert(key, provider)
+    }
+
+    pub fn remove(&self, key: &K) -> Option<Arc<dyn TokenProvider>> {
+        self.providers.write().unwrap().remove(key)
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.providers.read().unwrap().contains_key(key)
+    }
+
+    pub fn provider(&self, key: &K) -> Option<Arc<dyn TokenProvider>> {
+        self.providers.read().unwrap().get(key).cloned()
+    }
+
+    /// The provider registered under `key`, registering the one built by
+    /// `create` first if there is none. `create` runs without holding the
+    /// registry lock, so concurrent callers may each build a provider; the
+    /// first one registered wins.
+    pub fn get_or_insert_with(
+        &self,
+        key: K,
+        create: impl FnOnce() -> Result<Arc<dyn TokenProvider>, Error>,
+    ) -> Result<Arc<dyn TokenProvider>, Error> {
+        if let Some(provider) = self.provider(&key) {
+            return Ok(provider);
+        }
+        let provider = create()?;
+        let mut providers = self.providers.write().unwrap();
+        Ok(providers.entry(key).or_insert(provider).clone())
+    }
+
+    pub fn get_token(&self, key: &K) -> Result<AccessToken, Error> {
+        self.registered(key)?.get_token()
+    }
+
+    pub fn headers(&self, key: &K) -> Result<HeaderMap, Error> {
+        self.registered(key)?.headers()
+    }
+
+    pub fn invalidate(&self, key: &K) {
+        if let Some(provider) = self.provider(key) {
+            provider.invalidate();
+        }
+    }
+
+    pub fn invalidate_all(&self) {
+        for provider in self.providers.read().unwrap().values() {
+            provider.invalidate();
+        }
+    }
+
+    fn registered(&self, key: &K) -> Result<Arc<dyn TokenProvider>, Error> {
+        self.provider(key).ok_or_else(|| {
+            Error::InvalidCredentials(format!("no credentials registered for {:?}", key))
+        })
+    }
+}
+
+impl<K: Eq + Hash + Debug> Default for TokenManager<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FakeTokenProvider, StaticTokenProvider};
+    use std::sync::Barrier;
+    use std::thread;
+
+    #[test]
+    fn first_concurrent_registration_wins() {
+        let manager = Arc::new(TokenManager::new());
+        let barrier = Arc::new(Barrier::new(8));
+        let callers: Vec<_> = (0..8)
+            .map(|n| {
+                let (manager, barrier) = (manager.clone(), barrier.clone());
+                thread::spawn(move || {
+                    let provider = manager
+                        .get_or_insert_with("tenant", || {
+                            // Every caller has missed the registry by now.
+                            barrier.wait();
+                            Ok(Arc::new(StaticTokenProvider::new(format!("token-{}", n))))
+                        })
+                        .unwrap();
+                    provider.get_token().unwrap().value.clone()
+                })
+            })
+            .collect();
+        let values: Vec<String> = callers.into_iter().map(|c| c.join().unwrap()).collect();
+
+        let registered = manager.get_token(&"tenant").unwrap().value.clone();
+        assert!(
+            values.iter().all(|value| *value == registered),
+            "{:?}",
+            values
+        );
+    }
+
+    #[test]
+    fn rejects_unregistered_keys() {
+        let manager = TokenManager::new();
+        manager.insert("a", Arc::new(StaticTokenProvider::new("token")));
+
+        let err = manager.get_token(&"b").unwrap_err();
+        assert!(
+            matches!(&err, Error::InvalidCredentials(message) if message.contains("\"b\"")),
+            "{}",
+            err
+        );
+        assert!(manager.headers(&"b").is_err());
+    }
+
+    #[test]
+    fn invalidates_every_provider() {
+        let manager = TokenManager::new();
+        let providers: Vec<_> = (0..3).map(|_| Arc::new(FakeTokenProvider::new())).collect();
+        for (key, provider) in providers.iter().enumerate() {
+            manager.insert(key, provider.clone());
+        }
+
+        manager.invalidate(&0);
+        manager.invalidate_all();
+        let invalidations: Vec<_> = providers.iter().map(|p| p.invalidations()).collect();
+        assert_eq!(invalidations, [2, 1, 1]);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::cache::{CachedToken, CachedTokens};
+use crate::{
+    collect_scopes, default_headers, default_http_client, parse_response, AccessToken, Error,
+    RetryPolicy, TokenProvider, TokenResponse,
+};
+use reqwest::Client as HTTPClient;
+use std::time::Duration;
+
+const METADATA_URL: &str = "http://metadata.google.internal/computeMetadata/v1/";
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+const METADATA_IDENTITY_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/identity";
+
+pub struct MetadataClient {
+    scopes: String,
+    retry: RetryPolicy,
+    http: HTTPClient,
+    access_token: CachedToken,
+    id_tokens: CachedTokens,
+}
+
+impl MetadataClient {
+    pub fn new(scopes: impl IntoIterator<Item = impl AsRef<str>>) -> MetadataClient {
+        MetadataClient {
+            scopes: collect_scopes(scopes).join(","),
+            retry: RetryPolicy::default(),
+            http: default_http_client(),
+            access_token: CachedToken::default(),
+            id_tokens: CachedTokens::default(),
+        }
+    }
+
+    pub fn is_available() -> bool {
+        let http = match HTTPClient::builder()
+            .timeout(Duration::from_secs(1))
+            .build()
+        {
+            Ok(http) => http,
+            Err(_) => return false,
+        };
+
+        http.get(METADATA_URL)
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .map(|response| match response.headers().get("Metadata-Flavor") {
+                Some(flavor) => flavor == "Google",
+                None => false,
+            })
+            .unwrap_or(false)
+    }
+
+    pub fn http_client(mut self, http: HTTPClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn refresh_margin(mut self, margin: Duration) -> Self {
+        self.access_token = CachedToken::new(margin);
+        self.id_tokens = CachedTokens::new(margin);
+        self
+    }
+
+    pub fn get_token(&self) -> Result<AccessToken, Error> {
+        self.access_token.get_or_fetch(|| self.fetch_token())
+    }
+
+    pub fn force_refresh(&self) -> Result<AccessToken, Error> {
+        self.access_token.refresh(|| self.fetch_token())
+    }
+
+    pub fn invalidate(&self) {
+        self.access_token.invalidate();
+        self.id_tokens.invalidate();
+    }
+
+    pub fn get_id_token(&self, audience: &str) -> Result<AccessToken, Error> {
+        self.id_tokens
+            .get_or_fetch(audience, || self.fetch_id_token(audience))
+    }
+
+    fn fetch_token(&self) -> Result<AccessToken, Error> {
+        self.retry.retry(|| {
+            let mut request = self
+                .http
+                .get(METADATA_TOKEN_URL)
+                .headers(default_headers())
+                .header("Metadata-Flavor", "Google");
+            if !self.scopes.is_empty() {
+                request = request.query(&[("scopes", &self.scopes)]);
+            }
+
+            let token = parse_response::<TokenResponse>(request.send()?)?;
+            Ok(AccessToken::from(token))
+        })
+    }
+
+    fn fetch_id_token(&self, audience: &str) -> Result<AccessToken, Error> {
+        let token = self
+            .http
+            .get(METADATA_IDENTITY_URL)
+            .headers(default_headers())
+            .header("Metadata-Flavor", "Google")
+            .query(&[("audience", audience), ("format", "full")])
+            .send()?
+            .error_for_status()?
+            .text()?;
+        AccessToken::from_id_token(token)
+    }
+}
+
+impl TokenProvider for MetadataClient {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        MetadataClient::get_token(self)
+    }
+
+    fn invalidate(&self) {
+        MetadataClient::invalidate(self)
+    }
+
+    fn force_refresh(&self) -> Result<AccessToken, Error> {
+        MetadataClient::force_refresh(self)
+    }
+}
@@ -0,0 +1,335 @@
+use crate::{Credentials, Error, GRANT_TYPE};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAX_CLOCK_SKEW: u64 = 60;
+const MAX_ASSERTION_LIFETIME: u64 = 3600;
+
+/// An in-process stand-in for Google's token endpoint, for exercising the
+/// full fetch path offline.
+///
+/// Assertions are checked like the real endpoint would: the issuer and
+/// audience, the issue and expiry times, and, for RSA keys, the signature
+/// against `credentials`. Invalid assertions get an `invalid_grant` error.
+/// The server stops when dropped.
+pub struct MockTokenServer {
+    addr: SocketAddr,
+    state: Arc<State>,
+}
+
+struct State {
+    credentials: Credentials,
+    token_url: String,
+    responses: Mutex<VecDeque<MockResponse>>,
+    requests: Mutex<Vec<RecordedRequest>>,
+    shutdown: AtomicBool,
+}
+
+/// A canned token endpoint response.
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+/// A request the server received.
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub params: BTreeMap<String, String>,
+    /// The assertion's claims, when it could be decoded.
+    pub claims: Option<Value>,
+    /// Why the assertion was rejected, if it was.
+    pub rejection: Option<String>,
+}
+
+impl MockResponse {
+    pub fn token(access_token: impl Into<String>, expires_in: u64) -> Self {
+        Self::json(
+            200,
+            &json!({
+                "access_token": access_token.into(),
+                "token_type": "Bearer",
+                "expires_in": expires_in,
+            }),
+        )
+    }
+
+    pub fn error(status: u16, error: &str, description: &str) -> Self {
+        Self::json(
+            status,
+            &json!({ "error": error, "error_description": description }),
+        )
+    }
+
+    pub fn status(status: u16) -> Self {
+        MockResponse {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    pub fn json(status: u16, body: &Value) -> Self {
+        MockResponse {
+            body: body.to_string(),
+            ..Self::status(status)
+        }
+        .header("content-type", "application/json")
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+impl MockTokenServer {
+    /// Starts a server on a free local port that accepts assertions signed
+    /// by `credentials`.
+    pub fn start(credentials: Credentials) -> Result<Self, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(State {
+            credentials,
+            token_url: format!("http://{}/token", addr),
+            responses: Mutex::new(VecDeque::new()),
+            requests: Mutex::new(Vec::new()),
+            shutdown: AtomicBool::new(false),
+        });
+        let server_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if server_state.shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let _ = server_state.handle(stream);
+                }
+            }
+        });
+        Ok(MockTokenServer { addr, state })
+    }
+
+    /// The URL to pass to `ClientBuilder::token_url`.
+    pub fn token_url(&self) -> String {
+        self.state.token_url.clone()
+    }
+
+    /// Queues a response for the next valid assertion. Once the queue is
+    /// empty, valid assertions get a fresh token valid for an hour.
+    pub fn respond_with(&self, response: MockResponse) {
+        self.state.responses.lock().unwrap().push_back(response);
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockTokenServer {
+    fn drop(&mut self) {
+        self.state.shutdown.store(true, Ordering::SeqCst);
+        // Wake the accept loop so it sees the flag.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+impl State {
+    fn handle(&self, stream: TcpStream) -> Result<(), Error> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let path = request_line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or("/")
+            .to_owned();
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some(index) = line.find(':') {
+                headers.push((
+                    line[..index].trim().to_lowercase(),
+                    line[index + 1..].trim().to_owned(),
+                ));
+            }
+        }
+        let length = headers
+            .iter()
+            .find(|(name, _)| name == "content-length")
+            .and_then(|(_, value)| value.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+
+        let params = form_params(&String::from_utf8_lossy(&body));
+        let claims = params.get("assertion").and_then(|jwt| decode_claims(jwt));
+        let rejection = self.check(&params).err();
+        let response = match &rejection {
+            Some(reason) => MockResponse::error(400, "invalid_grant", reason),
+            None => self
+                .responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or_else(|| default_response(claims.as_ref())),
+        };
+        self.requests.lock().unwrap().push(RecordedRequest {
+            path,
+            headers,
+            params,
+            claims,
+            rejection,
+        });
+        write_response(stream, &response)
+    }
+
+    fn check(&self, params: &BTreeMap<String, String>) -> Result<(), String> {
+        if params.get("grant_type").map(String::as_str) != Some(GRANT_TYPE) {
+            return Err("unsupported grant_type".to_owned());
+        }
+        let assertion = params
+            .get("assertion")
+            .ok_or_else(|| "missing assertion".to_owned())?;
+        let parts: Vec<&str> = assertion.split('.').collect();
+        if parts.len() != 3 {
+            return Err("malformed assertion".to_owned());
+        }
+        let header =
+            decode_segment(parts[0]).ok_or_else(|| "malformed assertion header".to_owned())?;
+        let claims = decode_claims(assertion).ok_or_else(|| "malformed claims".to_owned())?;
+
+        let algorithm = header["alg"].as_str().unwrap_or_default();
+        if algorithm != self.credentials.signing_algorithm() {
+            return Err(format!("unexpected signing algorithm {}", algorithm));
+        }
+        // RS256 signatures are deterministic, so re-signing the input must
+        // reproduce them exactly.
+        if algorithm == "RS256" {
+            let signed = format!("{}.{}", parts[0], parts[1]);
+            let expected = self
+                .credentials
+                .sign_bytes(signed.as_bytes())
+                .map_err(|err| err.to_string())?;
+            if base64::decode_config(parts[2], base64::URL_SAFE_NO_PAD).ok() != Some(expected) {
+                return Err("invalid JWT signature".to_owned());
+            }
+        }
+
+        if claims["iss"].as_str() != Some(self.credentials.client_email()) {
+            return Err("unexpected issuer".to_owned());
+        }
+        if claims["aud"].as_str() != Some(self.token_url.as_str()) {
+            return Err("unexpected audience".to_owned());
+        }
+        if claims["scope"].is_null() && claims["target_audience"].is_null() {
+            return Err("missing scope or target_audience".to_owned());
+        }
+        let now = now();
+        let (iat, exp) = match (claims["iat"].as_u64(), claims["exp"].as_u64()) {
+            (Some(iat), Some(exp)) => (iat, exp),
+            _ => return Err("missing iat or exp".to_owned()),
+        };
+        if iat > now + MAX_CLOCK_SKEW || exp + MAX_CLOCK_SKEW < now {
+            return Err("Invalid JWT: Token must be a short-lived token".to_owned());
+        }
+        if exp < iat || exp - iat > MAX_ASSERTION_LIFETIME {
+            return Err("Invalid JWT: Token must be a short-lived token".to_owned());
+        }
+        Ok(())
+    }
+}
+
+/// A fresh access token, or an ID token for `target_audience` assertions.
+fn default_response(claims: Option<&Value>) -> MockResponse {
+    let audience = claims.and_then(|claims| claims["target_audience"].as_str());
+    let audience = match audience {
+        Some(audience) => audience,
+        None => return MockResponse::token(format!("mock-token-{}", now()), 3600),
+    };
+    let segment =
+        |value: Value| base64::encode_config(value.to_string().as_bytes(), base64::URL_SAFE_NO_PAD);
+    let id_token = format!(
+        "{}.{}.c2lnbmF0dXJl",
+        segment(json!({ "alg": "RS256", "typ": "JWT" })),
+        segment(json!({ "aud": audience, "iat": now(), "exp": now() + 3600 }))
+    );
+    MockResponse::json(200, &json!({ "id_token": id_token }))
+}
+
+fn write_response(mut stream: TcpStream, response: &MockResponse) -> Result<(), Error> {
+    let mut head = format!(
+        "HTTP/1.1 {} Mock\r\ncontent-length: {}\r\nconnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(response.body.as_bytes())?;
+    Ok(stream.flush()?)
+}
+
+fn decode_segment(segment: &str) -> Option<Value> {
+    let bytes = base64::decode_config(segment, base64::URL_SAFE_NO_PAD).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+fn decode_claims(jwt: &str) -> Option<Value> {
+    decode_segment(jwt.split('.').nth(1)?)
+}
+
+fn form_params(body: &str) -> BTreeMap<String, String> {
+    body.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            let name = percent_decode(parts.next().unwrap_or_default());
+            (name, percent_decode(parts.next().unwrap_or_default()))
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 2;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,114 @@
+use crate::secret::SecretBytes;
+use crate::Error;
+use reqwest::Identity;
+use std::env;
+#[cfg(feature = "openssl")]
+use std::path::PathBuf;
+
+/// Google's token endpoint for clients presenting a certificate.
+pub const MTLS_TOKEN_URL: &str = "https://oauth2.mtls.googleapis.com/token";
+const USE_CLIENT_CERTIFICATE_ENV_VAR: &str = "GOOGLE_API_USE_CLIENT_CERTIFICATE";
+
+/// A client certificate for mutual TLS, as required by certificate based
+/// access levels.
+#[derive(Clone)]
+pub struct ClientCertificate {
+    pkcs12: SecretBytes,
+    password: String,
+}
+
+#[cfg(feature = "openssl")]
+#[derive(serde::Deserialize, Debug)]
+struct ContextAwareMetadata {
+    cert_provider_command: Vec<String>,
+}
+
+impl ClientCertificate {
+    pub fn from_pkcs12_der(der: &[u8], password: impl Into<String>) -> Result<Self, Error> {
+        let certificate = ClientCertificate {
+            pkcs12: SecretBytes::new(der.to_vec()),
+            password: password.into(),
+        };
+        certificate.identity()?;
+        Ok(certificate)
+    }
+
+    /// `cert` holds the PEM certificate chain, leaf first, and `key` the PEM
+    /// private key. Both may point into the same buffer.
+    #[cfg(feature = "openssl")]
+    pub fn from_pem(cert: &[u8], key: &[u8]) -> Result<Self, Error> {
+        use openssl::pkcs12::Pkcs12;
+        use openssl::pkey::PKey;
+        use openssl::stack::Stack;
+        use openssl::x509::X509;
+
+        let mut chain = X509::stack_from_pem(cert)?.into_iter();
+        let leaf = chain.next().ok_or_else(|| {
+            Error::InvalidCredentials("client certificate PEM holds no certificate".to_owned())
+        })?;
+        let mut ca = Stack::new()?;
+        for cert in chain {
+            ca.push(cert)?;
+        }
+        let key = PKey::private_key_from_pem(key)?;
+        let pkcs12 = Pkcs12::builder()
+            .name("")
+            .pkey(&key)
+            .cert(&leaf)
+            .ca(ca)
+            .build2("")?;
+        Self::from_pkcs12_der(&pkcs12.to_der()?, "")
+    }
+
+    /// The certificate from the context aware access configuration written by
+    /// Endpoint Verification, or `None` when the device has none.
+    #[cfg(feature = "openssl")]
+    pub fn context_aware_default() -> Result<Option<Self>, Error> {
+        let path = match context_aware_metadata_file() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(None),
+        };
+        let metadata: ContextAwareMetadata = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let (program, args) = metadata
+            .cert_provider_command
+            .split_first()
+            .ok_or_else(|| {
+                Error::InvalidCredentials("cert_provider_command is empty".to_owned())
+            })?;
+        let output = std::process::Command::new(program).args(args).output()?;
+        if !output.status.success() {
+            return Err(Error::InvalidCredentials(format!(
+                "cert_provider_command failed with {}",
+                output.status
+            )));
+        }
+        // The command prints both the certificate chain and the key.
+        let output = SecretBytes::new(output.stdout);
+        Self::from_pem(&output, &output).map(Some)
+    }
+
+    pub(crate) fn identity(&self) -> Result<Identity, Error> {
+        Ok(Identity::from_pkcs12_der(&self.pkcs12, &self.password)?)
+    }
+}
+
+/// Whether `GOOGLE_API_USE_CLIENT_CERTIFICATE` opts into mutual TLS.
+pub fn use_client_certificate() -> bool {
+    env::var(USE_CLIENT_CERTIFICATE_ENV_VAR)
+        .map(|value| value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+#[cfg(feature = "openssl")]
+fn context_aware_metadata_file() -> Option<PathBuf> {
+    let home = if cfg!(windows) {
+        env::var_os("USERPROFILE")?
+    } else {
+        env::var_os("HOME")?
+    };
+    Some(
+        PathBuf::from(home)
+            .join(".secureConnect")
+            .join("context_aware_metadata.json"),
+    )
+}
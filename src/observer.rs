@@ -0,0 +1,27 @@
+use crate::{AccessToken, Error};
+use std::time::Duration;
+
+/// Receives events about token operations, for example to feed metrics.
+/// All methods default to doing nothing.
+pub trait TokenObserver: Send + Sync {
+    fn cache_hit(&self) {}
+
+    fn cache_miss(&self) {}
+
+    /// A token request succeeded after `elapsed`, including any retries.
+    fn fetched(&self, _elapsed: Duration) {}
+
+    /// A token request failed after `elapsed`, including any retries.
+    fn failed(&self, _error: &Error, _elapsed: Duration) {}
+
+    /// A new token was cached, replacing any previous one.
+    fn token_refreshed(&self, _token: &AccessToken) {}
+
+    /// Refreshing the cached token failed. `serving_stale` is set when the
+    /// old token, which has not expired yet, is handed out instead.
+    fn refresh_failed(&self, _error: &Error, _serving_stale: bool) {}
+
+    /// The cached token expired before it could be replaced, so callers get
+    /// errors until a refresh succeeds.
+    fn token_expired(&self, _token: &AccessToken) {}
+}
@@ -0,0 +1,56 @@
+use reqwest::{Proxy, Url};
+use std::env;
+
+// The metadata server is link-local and must never be reached through a proxy.
+const ALWAYS_DIRECT: &[&str] = &["metadata.google.internal", "169.254.169.254"];
+
+/// Builds a proxy from `HTTPS_PROXY`, `HTTP_PROXY` and `NO_PROXY`, checking
+/// both upper and lower case names.
+pub(crate) fn from_env() -> Option<Proxy> {
+    let https = env_url("HTTPS_PROXY");
+    let http = env_url("HTTP_PROXY");
+    if https.is_none() && http.is_none() {
+        return None;
+    }
+
+    let no_proxy: Vec<String> = env_var("NO_PROXY")
+        .map(|hosts| {
+            hosts
+                .split(',')
+                .map(|host| host.trim().trim_start_matches('.').to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Some(Proxy::custom(move |url| {
+        let host = url.host_str()?.to_lowercase();
+        let bypass = ALWAYS_DIRECT.iter().any(|direct| host == *direct)
+            || no_proxy.iter().any(|pattern| matches_host(&host, pattern));
+        if bypass {
+            return None;
+        }
+        match url.scheme() {
+            "https" => https.clone(),
+            "http" => http.clone(),
+            _ => None,
+        }
+    }))
+}
+
+fn matches_host(host: &str, pattern: &str) -> bool {
+    pattern == "*"
+        || host == pattern
+        || (host.ends_with(pattern) && host[..host.len() - pattern.len()].ends_with('.'))
+}
+
+fn env_url(name: &str) -> Option<Url> {
+    env_var(name).and_then(|url| Url::parse(&url).ok())
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name)
+        .or_else(|_| env::var(name.to_lowercase()))
+        .ok()
+        .filter(|value| !value.is_empty())
+}
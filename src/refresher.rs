@@ -0,0 +1,263 @@
+use crate::{AccessToken, Error, TokenProvider};
+use std::cmp;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+const REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const FAILURE_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+pub struct BackgroundRefresher<P> {
+    shared: Arc<Shared<P>>,
+}
+
+struct Shared<P> {
+    provider: P,
+    token: RwLock<Option<AccessToken>>,
+    stopped: Mutex<bool>,
+    wakeup: Condvar,
+    updates: Arc<Updates>,
+}
+
+/// Yields each token the refresher obtains, for long lived connections that
+/// swap credentials mid-stream. Ends once the refresher is dropped.
+pub struct TokenSubscription {
+    updates: Arc<Updates>,
+    seen: u64,
+}
+
+#[derive(Default)]
+struct Updates {
+    published: Mutex<Published>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct Published {
+    token: Option<AccessToken>,
+    version: u64,
+    closed: bool,
+}
+
+impl<P: TokenProvider + 'static> BackgroundRefresher<P> {
+    pub fn new(provider: P) -> Self {
+        let shared = Arc::new(Shared {
+            provider,
+            token: RwLock::new(None),
+            stopped: Mutex::new(false),
+            wakeup: Condvar::new(),
+            updates: Arc::default(),
+        });
+
+        let background = shared.clone();
+        thread::spawn(move || background.run());
+        BackgroundRefresher { shared }
+    }
+
+    pub fn get_token(&self) -> Result<AccessToken, Error> {
+        match &*self.shared.token.read().unwrap() {
+            Some(token) if !token.expired() => return Ok(token.clone()),
+            _ => {}
+        }
+
+        self.shared.refresh()
+    }
+
+    pub fn invalidate(&self) {
+        *self.shared.token.write().unwrap() = None;
+        self.shared.provider.invalidate();
+    }
+
+    /// The first item is the current token, if there is one yet.
+    pub fn subscribe(&self) -> TokenSubscription {
+        TokenSubscription {
+            updates: self.shared.updates.clone(),
+            seen: 0,
+        }
+    }
+}
+
+impl TokenSubscription {
+    pub fn latest(&self) -> Option<AccessToken> {
+        self.updates.published.lock().unwrap().token.clone()
+    }
+
+    /// Like `next`, but gives up after `timeout`.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<AccessToken> {
+        self.wait(Some(timeout))
+    }
+
+    fn wait(&mut self, timeout: Option<Duration>) -> Option<AccessToken> {
+        let seen = self.seen;
+        let pending = |published: &mut Published| published.version == seen && !published.closed;
+        let published = self.updates.published.lock().unwrap();
+        let published = match timeout {
+            Some(timeout) => {
+                self.updates
+                    .changed
+                    .wait_timeout_while(published, timeout, pending)
+                    .unwrap()
+                    .0
+            }
+            None => self.updates.changed.wait_while(published, pending).unwrap(),
+        };
+        if published.version == seen {
+            return None;
+        }
+        self.seen = published.version;
+        published.token.clone()
+    }
+}
+
+impl Iterator for TokenSubscription {
+    type Item = AccessToken;
+
+    /// Blocks until a token newer than the last one yielded arrives.
+    fn next(&mut self) -> Option<AccessToken> {
+        self.wait(None)
+    }
+}
+
+impl Updates {
+    /// Wakes subscribers only when `token` differs from the published one.
+    fn publish(&self, token: &AccessToken) {
+        let mut published = self.published.lock().unwrap();
+        if matches!(&published.token, Some(current) if current.value == token.value) {
+            return;
+        }
+        published.token = Some(token.clone());
+        published.version += 1;
+        self.changed.notify_all();
+    }
+
+    fn close(&self) {
+        self.published.lock().unwrap().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+impl<P: TokenProvider> Shared<P> {
+    fn run(&self) {
+        let mut result = self.refresh();
+        loop {
+            // Tokens shorter lived than the margin are renewed halfway.
+            let wait = match result {
+                Ok(token) => {
+                    let expires_in = token.expires_in();
+                    expires_in
+                        .checked_sub(REFRESH_MARGIN)
+                        .unwrap_or(expires_in / 2)
+                }
+                Err(_) => FAILURE_RETRY_INTERVAL,
+            };
+
+            let stopped = self.stopped.lock().unwrap();
+            let (stopped, _) = self
+                .wakeup
+                .wait_timeout_while(stopped, cmp::max(wait, MIN_REFRESH_INTERVAL), |stopped| {
+                    !*stopped
+                })
+                .unwrap();
+            if *stopped {
+                return;
+            }
+            drop(stopped);
+            // The provider's cached token may still be outside its own,
+            // shorter, refresh margin.
+            result = self.store(self.provider.force_refresh());
+        }
+    }
+
+    fn refresh(&self) -> Result<AccessToken, Error> {
+        self.store(self.provider.get_token())
+    }
+
+    fn store(&self, token: Result<AccessToken, Error>) -> Result<AccessToken, Error> {
+        let token = token?;
+        *self.token.write().unwrap() = Some(token.clone());
+        self.updates.publish(&token);
+        Ok(token)
+    }
+}
+
+impl<P: TokenProvider + 'static> TokenProvider for BackgroundRefresher<P> {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        BackgroundRefresher::get_token(self)
+    }
+
+    fn invalidate(&self) {
+        BackgroundRefresher::invalidate(self)
+    }
+
+    fn quota_project_id(&self) -> Option<&str> {
+        self.shared.provider.quota_project_id()
+    }
+
+    fn universe_domain(&self) -> &str {
+        self.shared.provider.universe_domain()
+    }
+}
+
+impl<P> Drop for BackgroundRefresher<P> {
+    fn drop(&mut self) {
+        *self.shared.stopped.lock().unwrap() = true;
+        self.shared.wakeup.notify_all();
+        self.shared.updates.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FakeTokenProvider;
+    use std::time::SystemTime;
+
+    struct Counted(Arc<FakeTokenProvider>);
+
+    impl TokenProvider for Counted {
+        fn get_token(&self) -> Result<AccessToken, Error> {
+            self.0.get_token()
+        }
+
+        fn invalidate(&self) {
+            self.0.invalidate()
+        }
+    }
+
+    #[test]
+    fn does_not_spin_on_tokens_inside_the_margin() {
+        let expires = SystemTime::now() + Duration::from_secs(4 * 60);
+        let provider = Arc::new(FakeTokenProvider::with_token(AccessToken::new(
+            "t", expires,
+        )));
+        let refresher = BackgroundRefresher::new(Counted(provider.clone()));
+        thread::sleep(Duration::from_millis(1500));
+        drop(refresher);
+        assert_eq!(provider.calls(), 1);
+        assert_eq!(provider.invalidations(), 0);
+    }
+
+    #[test]
+    fn subscribers_only_see_new_tokens() {
+        let updates = Updates::default();
+        let mut subscription = TokenSubscription {
+            updates: Arc::new(updates),
+            seen: 0,
+        };
+        let expires = SystemTime::now() + Duration::from_secs(3600);
+        subscription
+            .updates
+            .publish(&AccessToken::new("a", expires));
+        subscription
+            .updates
+            .publish(&AccessToken::new("a", expires));
+        let timeout = Duration::from_millis(10);
+        assert_eq!(subscription.next_timeout(timeout).unwrap().value, "a");
+        assert!(subscription.next_timeout(timeout).is_none());
+        subscription
+            .updates
+            .publish(&AccessToken::new("b", expires));
+        assert_eq!(subscription.next_timeout(timeout).unwrap().value, "b");
+    }
+}
@@ -0,0 +1,147 @@
+use crate::Error;
+use rand::Rng;
+use std::cmp;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    pub(crate) fn retry<T>(&self, mut f: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+        let mut attempt = 1;
+        loop {
+            let result = f();
+            let delay = match &result {
+                Err(err) if attempt < self.max_attempts && err.is_retryable() => {
+                    self.delay(attempt, err)
+                }
+                _ => None,
+            };
+            match (result, delay) {
+                (Err(err), Some(delay)) => {
+                    log_debug!(
+                        "token request attempt {}/{} failed, retrying in {:?}: {}",
+                        attempt,
+                        self.max_attempts,
+                        delay,
+                        err
+                    );
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                (result, _) => return result,
+            }
+        }
+    }
+
+    /// The wait before the next attempt, or `None` when the endpoint asked
+    /// for a longer wait than `max_delay`.
+    fn delay(&self, attempt: u32, err: &Error) -> Option<Duration> {
+        if let Error::Status {
+            retry_after: Some(retry_after),
+            ..
+        } = err
+        {
+            return Some(*retry_after).filter(|delay| *delay <= self.max_delay);
+        }
+
+        let backoff = self
+            .base_delay
+            .checked_mul(1 << cmp::min(attempt - 1, 16))
+            .map_or(self.max_delay, |backoff| cmp::min(backoff, self.max_delay));
+        if self.jitter {
+            let millis = backoff.as_millis() as u64;
+            Some(Duration::from_millis(
+                rand::thread_rng().gen_range(0, millis + 1),
+            ))
+        } else {
+            Some(backoff)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::StatusCode;
+
+    fn unavailable(retry_after: Option<u64>) -> Error {
+        Error::Status {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            retry_after: retry_after.map(Duration::from_secs),
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_secs(1),
+            jitter: false,
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_max_delay() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(300),
+            ..policy()
+        };
+        let delays: Vec<_> = (1..5)
+            .map(|n| policy.delay(n, &unavailable(None)))
+            .collect();
+        let millis = |ms| Some(Duration::from_millis(ms));
+        assert_eq!(
+            delays,
+            [millis(300), millis(600), millis(1000), millis(1000)]
+        );
+    }
+
+    #[test]
+    fn gives_up_on_retry_after_beyond_max_delay() {
+        let mut calls = 0;
+        let result: Result<(), Error> = policy().retry(|| {
+            calls += 1;
+            Err(unavailable(Some(86400)))
+        });
+        assert!(matches!(result, Err(Error::Status { .. })));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn retries_retryable_errors() {
+        let mut calls = 0;
+        let result = policy().retry(|| {
+            calls += 1;
+            match calls {
+                1 => Err(unavailable(Some(0))),
+                _ => Ok(calls),
+            }
+        });
+        assert_eq!(result.unwrap(), 2);
+    }
+}
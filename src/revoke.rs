@@ -0,0 +1,29 @@
+use crate::{default_headers, default_http_client, Error, OAuthErrorResponse};
+use reqwest::Client as HTTPClient;
+
+const REVOKE_URL: &str = "https://oauth2.googleapis.com/revoke";
+
+/// Revokes an access or refresh token. Revoking a refresh token also revokes
+/// the access tokens issued from it.
+pub fn revoke_token(token: &str) -> Result<(), Error> {
+    revoke(&default_http_client(), token)
+}
+
+pub(crate) fn revoke(http: &HTTPClient, token: &str) -> Result<(), Error> {
+    let mut response = http
+        .post(REVOKE_URL)
+        .headers(default_headers())
+        .form(&[("token", token)])
+        .send()?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    Err(match response.json::<OAuthErrorResponse>() {
+        Ok(response) => Error::from(response),
+        Err(_) => Error::Status {
+            status,
+            retry_after: None,
+        },
+    })
+}
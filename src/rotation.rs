@@ -0,0 +1,201 @@
+use crate::{collect_scopes, AccessToken, Client, Credentials, Error, TokenProvider};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+type BuildClient = dyn Fn(Credentials) -> Result<Client, Error> + Send + Sync;
+
+/// A client for one service account holding several of its keys, for zero
+/// downtime key rotation. When the key in use is rejected with
+/// `invalid_grant`, e.g. because it was deleted, the next key is tried and
+/// kept from then on.
+pub struct KeyRotationClient {
+    client_email: String,
+    keys: RwLock<Vec<Key>>,
+    active: AtomicUsize,
+    build: Box<BuildClient>,
+}
+
+#[derive(Clone)]
+struct Key {
+    id: Option<String>,
+    client: Client,
+}
+
+impl KeyRotationClient {
+    pub fn new(
+        keys: impl IntoIterator<Item = Credentials>,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Self, Error> {
+        let scopes = collect_scopes(scopes);
+        Self::with_builder(keys, move |credentials| {
+            Ok(Client::new(credentials, &scopes))
+        })
+    }
+
+    /// Like `new`, with `build` creating the client for each key, e.g. to
+    /// apply `ClientBuilder` options.
+    pub fn with_builder(
+        keys: impl IntoIterator<Item = Credentials>,
+        build: impl Fn(Credentials) -> Result<Client, Error> + Send + Sync + 'static,
+    ) -> Result<Self, Error> {
+        let mut keys = keys.into_iter();
+        let first = keys
+            .next()
+            .ok_or_else(|| Error::InvalidCredentials("no keys given".to_owned()))?;
+        let client = KeyRotationClient {
+            client_email: first.client_email().to_owned(),
+            keys: RwLock::new(Vec::new()),
+            active: AtomicUsize::new(0),
+            build: Box::new(build),
+        };
+        client.add_key(first)?;
+        for key in keys {
+            client.add_key(key)?;
+        }
+        Ok(client)
+    }
+
+    /// Adds a newly created key, tried after the existing ones.
+    pub fn add_key(&self, credentials: Credentials) -> Result<(), Error> {
+        if credentials.client_email() != self.client_email {
+            return Err(Error::InvalidCredentials(format!(
+                "key for {} does not belong to {}",
+                credentials.client_email(),
+                self.client_email
+            )));
+        }
+        let key = Key {
+            id: credentials.private_key_id().map(str::to_owned),
+            client: (self.build)(credentials)?,
+        };
+        self.keys.write().unwrap().push(key);
+        Ok(())
+    }
+
+    /// Stops using the key with `private_key_id`, returning whether it was
+    /// registered. The last key cannot be removed.
+    pub fn remove_key(&self, private_key_id: &str) -> bool {
+        let mut keys = self.keys.write().unwrap();
+        let index = match keys
+            .iter()
+            .position(|key| key.id.as_deref() == Some(private_key_id))
+        {
+            Some(index) if keys.len() > 1 => index,
+            _ => return false,
+        };
+        keys.remove(index);
+        let active = self.active.load(Ordering::SeqCst);
+        if active > index || active >= keys.len() {
+            self.active
+                .store(active.saturating_sub(1), Ordering::SeqCst);
+        }
+        true
+    }
+
+    /// The `private_key_id` of the key in use.
+    pub fn active_key_id(&self) -> Option<String> {
+        let keys = self.keys.read().unwrap();
+        keys[self.active.load(Ordering::SeqCst) % keys.len()]
+            .id
+            .clone()
+    }
+
+    pub fn get_token(&self) -> Result<AccessToken, Error> {
+        // Fetch without the lock, so adding or removing keys never waits on
+        // the network.
+        let (keys, start) = {
+            let keys = self.keys.read().unwrap();
+            let start = self.active.load(Ordering::SeqCst) % keys.len();
+            (keys.clone(), start)
+        };
+        let mut rejected = None;
+        for offset in 0..keys.len() {
+            let index = (start + offset) % keys.len();
+            let key = &keys[index];
+            match key.client.get_token() {
+                Ok(token) => {
+                    if index != start {
+                        log_warn!("switched to key {:?} of {}", key.id, self.client_email);
+                        self.active.store(index, Ordering::SeqCst);
+                    }
+                    return Ok(token);
+                }
+                Err(err) if is_rejected(&err) => {
+                    log_warn!(
+                        "key {:?} of {} was rejected: {}",
+                        key.id,
+                        self.client_email,
+                        err
+                    );
+                    rejected = Some(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(rejected.unwrap())
+    }
+
+    pub fn invalidate(&self) {
+        for key in self.keys.read().unwrap().iter() {
+            key.client.invalidate();
+        }
+    }
+}
+
+/// Whether `err` means the key itself is no longer accepted.
+fn is_rejected(err: &Error) -> bool {
+    match err {
+        Error::OAuth { error, .. } => error == "invalid_grant",
+        Error::Shared(err) => is_rejected(err),
+        _ => false,
+    }
+}
+
+impl TokenProvider for KeyRotationClient {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        KeyRotationClient::get_token(self)
+    }
+
+    fn invalidate(&self) {
+        KeyRotationClient::invalidate(self)
+    }
+}
+
+#[cfg(all(test, feature = "test-utils"))]
+mod tests {
+    use super::*;
+    use crate::{MockResponse, MockTokenServer};
+
+    const EMAIL: &str = "test@example-project.iam.gserviceaccount.com";
+
+    fn key(pem: &str, id: &str) -> Credentials {
+        let mut credentials = Credentials::from_pem(pem, EMAIL).unwrap();
+        credentials.private_key_id = Some(id.to_owned());
+        credentials
+    }
+
+    #[test]
+    fn fails_over_to_the_next_key() {
+        let old = key(include_str!("../testdata/rsa_pkcs1.pem"), "k1");
+        let new = key(include_str!("../testdata/other_rsa_pkcs1.pem"), "k2");
+        // Only the new key is accepted, as if the old one had been deleted.
+        let server = MockTokenServer::start(new.clone()).unwrap();
+        server.respond_with(MockResponse::token("token-2", 3600));
+        let token_url = server.token_url();
+        let client = KeyRotationClient::with_builder(vec![old, new], move |credentials| {
+            Client::builder(credentials, ["scope"])
+                .token_url(token_url.clone())
+                .build()
+        })
+        .unwrap();
+        assert_eq!(client.active_key_id().as_deref(), Some("k1"));
+
+        assert_eq!(client.get_token().unwrap().value, "token-2");
+        assert_eq!(client.active_key_id().as_deref(), Some("k2"));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].rejection.is_some());
+        assert_eq!(requests[1].rejection, None);
+    }
+}
@@ -0,0 +1,68 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    CloudPlatform,
+    CloudPlatformReadOnly,
+    DevstorageFullControl,
+    DevstorageReadOnly,
+    DevstorageReadWrite,
+    PubSub,
+    BigQuery,
+    BigQueryReadOnly,
+    Datastore,
+    LoggingWrite,
+    Monitoring,
+    FirebaseMessaging,
+    GmailSend,
+    GmailReadOnly,
+    Drive,
+    DriveReadOnly,
+    Spreadsheets,
+    Calendar,
+    AdminDirectoryUser,
+    UserinfoEmail,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::CloudPlatform => "https://www.googleapis.com/auth/cloud-platform",
+            Scope::CloudPlatformReadOnly => {
+                "https://www.googleapis.com/auth/cloud-platform.read-only"
+            }
+            Scope::DevstorageFullControl => {
+                "https://www.googleapis.com/auth/devstorage.full_control"
+            }
+            Scope::DevstorageReadOnly => "https://www.googleapis.com/auth/devstorage.read_only",
+            Scope::DevstorageReadWrite => "https://www.googleapis.com/auth/devstorage.read_write",
+            Scope::PubSub => "https://www.googleapis.com/auth/pubsub",
+            Scope::BigQuery => "https://www.googleapis.com/auth/bigquery",
+            Scope::BigQueryReadOnly => "https://www.googleapis.com/auth/bigquery.readonly",
+            Scope::Datastore => "https://www.googleapis.com/auth/datastore",
+            Scope::LoggingWrite => "https://www.googleapis.com/auth/logging.write",
+            Scope::Monitoring => "https://www.googleapis.com/auth/monitoring",
+            Scope::FirebaseMessaging => "https://www.googleapis.com/auth/firebase.messaging",
+            Scope::GmailSend => "https://www.googleapis.com/auth/gmail.send",
+            Scope::GmailReadOnly => "https://www.googleapis.com/auth/gmail.readonly",
+            Scope::Drive => "https://www.googleapis.com/auth/drive",
+            Scope::DriveReadOnly => "https://www.googleapis.com/auth/drive.readonly",
+            Scope::Spreadsheets => "https://www.googleapis.com/auth/spreadsheets",
+            Scope::Calendar => "https://www.googleapis.com/auth/calendar",
+            Scope::AdminDirectoryUser => "https://www.googleapis.com/auth/admin.directory.user",
+            Scope::UserinfoEmail => "https://www.googleapis.com/auth/userinfo.email",
+        }
+    }
+}
+
+impl AsRef<str> for Scope {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
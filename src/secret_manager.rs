@@ -0,0 +1,191 @@
+use crate::{
+    default_headers, default_http_client, parse_response, AccessToken, Client, Credentials, Error,
+    MetadataClient, Scope, TokenProvider,
+};
+use reqwest::Client as HTTPClient;
+use serde::Deserialize;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Deserialize, Debug)]
+struct AccessSecretVersionResponse {
+    name: String,
+    payload: SecretPayload,
+}
+
+#[derive(Deserialize, Debug)]
+struct SecretPayload {
+    data: String,
+}
+
+/// A service account JSON key stored in Secret Manager, so it never has to
+/// be written to disk.
+pub struct SecretManagerCredentials {
+    name: String,
+    provider: Arc<dyn TokenProvider>,
+    http: HTTPClient,
+    endpoint: String,
+}
+
+impl SecretManagerCredentials {
+    /// `name` is a secret version, e.g.
+    /// `projects/p/secrets/s/versions/latest`, or a secret, which reads
+    /// its latest version. `provider` authorizes the calls to Secret
+    /// Manager.
+    pub fn new(name: impl Into<String>, provider: Arc<dyn TokenProvider>) -> Self {
+        let mut name = name.into();
+        if !name.contains("/versions/") {
+            name.push_str("/versions/latest");
+        }
+        SecretManagerCredentials {
+            endpoint: format!("https://secretmanager.{}/v1", provider.universe_domain()),
+            name,
+            provider,
+            http: default_http_client(),
+        }
+    }
+
+    /// Reads the secret with the credentials of the VM or container, from
+    /// the metadata server.
+    pub fn from_metadata(name: impl Into<String>) -> Self {
+        let provider = MetadataClient::new([Scope::CloudPlatform.as_str()]);
+        Self::new(name, Arc::new(provider))
+    }
+
+    pub fn http_client(mut self, http: HTTPClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn fetch(&self) -> Result<Credentials, Error> {
+        self.fetch_version().map(|(_, credentials)| credentials)
+    }
+
+    /// The credentials along with the full name of the version they came
+    /// from, which resolves aliases such as `latest`.
+    fn fetch_version(&self) -> Result<(String, Credentials), Error> {
+        let token = self.provider.get_token()?;
+        let response = self
+            .http
+            .get(&format!("{}/{}:access", self.endpoint, self.name))
+            .headers(default_headers())
+            .bearer_auth(&token.value)
+            .send()?;
+        let response = parse_response::<AccessSecretVersionResponse>(response)?;
+        let json = base64::decode(&response.payload.data)
+            .ok()
+            .and_then(|json| String::from_utf8(json).ok())
+            .ok_or_else(|| {
+                Error::InvalidCredentials(format!("secret {} is not a JSON key", response.name))
+            })?;
+        Ok((response.name, Credentials::from_json_str(&json)?))
+    }
+}
+
+impl fmt::Debug for SecretManagerCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SecretManagerCredentials")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+/// A service account client whose key is read from Secret Manager, and
+/// read again every few minutes so that newly added versions are picked up
+/// without a restart.
+pub struct SecretManagerClient {
+    source: SecretManagerCredentials,
+    scopes: Vec<String>,
+    check_interval: Duration,
+    state: Mutex<State>,
+}
+
+struct State {
+    version: String,
+    client: Client,
+    checked: Instant,
+}
+
+impl SecretManagerClient {
+    /// Reads the key right away, failing if it cannot be loaded.
+    pub fn new(
+        source: SecretManagerCredentials,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Self, Error> {
+        let scopes: Vec<String> = scopes.into_iter().map(|s| s.as_ref().to_owned()).collect();
+        let (version, credentials) = source.fetch_version()?;
+        Ok(SecretManagerClient {
+            state: Mutex::new(State {
+                version,
+                client: Client::new(credentials, &scopes),
+                checked: Instant::now(),
+            }),
+            source,
+            scopes,
+            check_interval: DEFAULT_CHECK_INTERVAL,
+        })
+    }
+
+    /// How often to check for a new version. Defaults to five minutes.
+    pub fn check_interval(mut self, interval: Duration) -> Self {
+        self.check_interval = interval;
+        self
+    }
+
+    /// The full name of the secret version in use.
+    pub fn version(&self) -> String {
+        self.state.lock().unwrap().version.clone()
+    }
+
+    pub fn get_token(&self) -> Result<AccessToken, Error> {
+        self.client().get_token()
+    }
+
+    pub fn invalidate(&self) {
+        self.state.lock().unwrap().client.invalidate();
+    }
+
+    fn client(&self) -> Client {
+        let mut state = self.state.lock().unwrap();
+        if state.checked.elapsed() >= self.check_interval {
+            state.checked = Instant::now();
+            match self.source.fetch_version() {
+                Ok((version, credentials)) if version != state.version => {
+                    log_debug!("switching to service account key {}", version);
+                    state.client = Client::new(credentials, &self.scopes);
+                    state.version = version;
+                }
+                Ok(_) => {}
+                // Keep using the key we have until the next check.
+                Err(err) => log_warn!("failed to check {}: {}", self.source.name, err),
+            }
+        }
+        state.client.clone()
+    }
+}
+
+impl TokenProvider for SecretManagerClient {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        SecretManagerClient::get_token(self)
+    }
+
+    fn invalidate(&self) {
+        SecretManagerClient::invalidate(self)
+    }
+
+    fn universe_domain(&self) -> &str {
+        self.source.provider.universe_domain()
+    }
+}
@@ -0,0 +1,14 @@
+use crate::Error;
+
+/// Produces JWT signatures, for keys that are held outside the process such
+/// as in an HSM, Cloud KMS or an OS keystore.
+pub trait Signer: Send + Sync {
+    /// The JWS `alg` of the signatures, e.g. `RS256` or `ES256`.
+    fn algorithm(&self) -> &str {
+        "RS256"
+    }
+
+    /// Signs `message`, the encoded JWT header and claims, returning the raw
+    /// signature bytes.
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error>;
+}
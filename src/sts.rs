@@ -0,0 +1,165 @@
+use crate::{
+    collect_scopes, default_headers, default_http_client, parse_response, AccessToken, Error,
+    DEFAULT_UNIVERSE_DOMAIN,
+};
+use reqwest::Client as HTTPClient;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+pub(crate) const TOKEN_EXCHANGE_GRANT_TYPE: &str =
+    "urn:ietf:params:oauth:grant-type:token-exchange";
+pub(crate) const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+/// An RFC 8693 token exchange request.
+#[derive(Clone)]
+pub struct TokenExchangeRequest {
+    subject_token: String,
+    subject_token_type: String,
+    requested_token_type: String,
+    audience: Option<String>,
+    scopes: Vec<String>,
+    options: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TokenExchangeResponse {
+    pub access_token: String,
+    pub issued_token_type: String,
+    pub token_type: String,
+    /// Absent when the subject token has no expiry of its own.
+    pub expires_in: Option<u64>,
+}
+
+impl fmt::Debug for TokenExchangeRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TokenExchangeRequest")
+            .field("subject_token", &"<redacted>")
+            .field("subject_token_type", &self.subject_token_type)
+            .field("requested_token_type", &self.requested_token_type)
+            .field("audience", &self.audience)
+            .field("scopes", &self.scopes)
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+impl fmt::Debug for TokenExchangeResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TokenExchangeResponse")
+            .field("access_token", &"<redacted>")
+            .field("issued_token_type", &self.issued_token_type)
+            .field("token_type", &self.token_type)
+            .field("expires_in", &self.expires_in)
+            .finish()
+    }
+}
+
+/// A client for the Security Token Service, which workload identity
+/// federation and downscoping are built on.
+pub struct StsClient {
+    token_url: String,
+    http: HTTPClient,
+}
+
+impl TokenExchangeRequest {
+    pub fn new(subject_token: impl Into<String>, subject_token_type: impl Into<String>) -> Self {
+        TokenExchangeRequest {
+            subject_token: subject_token.into(),
+            subject_token_type: subject_token_type.into(),
+            requested_token_type: ACCESS_TOKEN_TYPE.to_owned(),
+            audience: None,
+            scopes: Vec::new(),
+            options: None,
+        }
+    }
+
+    pub fn requested_token_type(mut self, token_type: impl Into<String>) -> Self {
+        self.requested_token_type = token_type.into();
+        self
+    }
+
+    pub fn audience(mut self, audience: impl Into<String>) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+
+    pub fn scopes(mut self, scopes: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        self.scopes = collect_scopes(scopes);
+        self
+    }
+
+    /// The JSON encoded `options` parameter, e.g. an access boundary.
+    pub fn options(mut self, options: impl Into<String>) -> Self {
+        self.options = Some(options.into());
+        self
+    }
+}
+
+impl TokenExchangeResponse {
+    /// Uses `fallback` as the expiry when the response carries none.
+    pub fn into_access_token(self, fallback: SystemTime) -> AccessToken {
+        let expires = match self.expires_in {
+            Some(expires_in) => SystemTime::now() + Duration::from_secs(expires_in),
+            None => fallback,
+        };
+        AccessToken::new(self.access_token, expires)
+    }
+}
+
+/// The Security Token Service endpoint of a universe.
+pub(crate) fn sts_token_url(universe_domain: &str) -> String {
+    format!("https://sts.{}/v1/token", universe_domain)
+}
+
+impl Default for StsClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StsClient {
+    pub fn new() -> StsClient {
+        StsClient {
+            token_url: sts_token_url(DEFAULT_UNIVERSE_DOMAIN),
+            http: default_http_client(),
+        }
+    }
+
+    pub fn token_url(mut self, token_url: impl Into<String>) -> Self {
+        self.token_url = token_url.into();
+        self
+    }
+
+    pub fn http_client(mut self, http: HTTPClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn exchange(&self, request: &TokenExchangeRequest) -> Result<TokenExchangeResponse, Error> {
+        let scopes = request.scopes.join(" ");
+        let mut params = HashMap::new();
+        params.insert("grant_type", TOKEN_EXCHANGE_GRANT_TYPE);
+        params.insert("subject_token", &request.subject_token);
+        params.insert("subject_token_type", &request.subject_token_type);
+        params.insert("requested_token_type", &request.requested_token_type);
+        if let Some(audience) = &request.audience {
+            params.insert("audience", audience);
+        }
+        if !scopes.is_empty() {
+            params.insert("scope", &scopes);
+        }
+        if let Some(options) = &request.options {
+            params.insert("options", options);
+        }
+
+        let response = self
+            .http
+            .post(&self.token_url)
+            .headers(default_headers())
+            .form(&params)
+            .send()?;
+        parse_response(response)
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{default_http_client, Error};
+use reqwest::Client as HTTPClient;
+use std::fs;
+use std::path::PathBuf;
+
+/// Supplies the external subject token that an `ExternalAccountClient`
+/// exchanges for a Google access token, e.g. an OIDC ID token.
+pub trait SubjectTokenSupplier: Send + Sync {
+    fn subject_token(&self) -> Result<String, Error>;
+}
+
+/// How a subject token is stored in a file or URL response.
+#[derive(Debug, Clone)]
+pub enum SubjectTokenFormat {
+    /// The whole content, trimmed.
+    Text,
+    /// A string field of a JSON object.
+    Json(String),
+}
+
+/// Reads the subject token from a file, re-reading it on every exchange so
+/// rotated tokens are picked up.
+#[derive(Debug, Clone)]
+pub struct FileTokenSupplier {
+    path: PathBuf,
+    format: SubjectTokenFormat,
+}
+
+/// Fetches the subject token from a URL, such as a local token server.
+pub struct UrlTokenSupplier {
+    url: String,
+    headers: Vec<(String, String)>,
+    format: SubjectTokenFormat,
+    http: HTTPClient,
+}
+
+impl FileTokenSupplier {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileTokenSupplier {
+            path: path.into(),
+            format: SubjectTokenFormat::Text,
+        }
+    }
+
+    pub fn format(mut self, format: SubjectTokenFormat) -> Self {
+        self.format = format;
+        self
+    }
+}
+
+impl SubjectTokenSupplier for FileTokenSupplier {
+    fn subject_token(&self) -> Result<String, Error> {
+        self.format.extract(&fs::read_to_string(&self.path)?)
+    }
+}
+
+impl UrlTokenSupplier {
+    pub fn new(url: impl Into<String>) -> Self {
+        UrlTokenSupplier {
+            url: url.into(),
+            headers: Vec::new(),
+            format: SubjectTokenFormat::Text,
+            http: default_http_client(),
+        }
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn format(mut self, format: SubjectTokenFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn http_client(mut self, http: HTTPClient) -> Self {
+        self.http = http;
+        self
+    }
+}
+
+impl SubjectTokenSupplier for UrlTokenSupplier {
+    fn subject_token(&self) -> Result<String, Error> {
+        let mut request = self.http.get(&self.url);
+        for (name, value) in &self.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        let raw = request.send()?.error_for_status()?.text()?;
+        self.format.extract(&raw)
+    }
+}
+
+impl SubjectTokenFormat {
+    fn extract(&self, raw: &str) -> Result<String, Error> {
+        match self {
+            SubjectTokenFormat::Json(field) => {
+                let value: serde_json::Value = serde_json::from_str(raw)?;
+                value[field.as_str()]
+                    .as_str()
+                    .map(str::to_owned)
+                    .ok_or_else(|| {
+                        Error::InvalidCredentials(
+                            "subject token field missing from credential source".to_owned(),
+                        )
+                    })
+            }
+            SubjectTokenFormat::Text => Ok(raw.trim().to_owned()),
+        }
+    }
+}
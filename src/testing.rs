@@ -0,0 +1,123 @@
+use crate::{AccessToken, Error, TokenProvider};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// A provider that always returns the same token, for testing code that
+/// only needs some credentials. No keys or network are involved.
+#[derive(Debug, Clone)]
+pub struct StaticTokenProvider {
+    token: AccessToken,
+    quota_project_id: Option<String>,
+}
+
+impl StaticTokenProvider {
+    /// A token valid for an hour.
+    pub fn new(value: impl Into<String>) -> Self {
+        Self::from_token(AccessToken::new(
+            value,
+            SystemTime::now() + Duration::from_secs(3600),
+        ))
+    }
+
+    pub fn from_token(token: AccessToken) -> Self {
+        StaticTokenProvider {
+            token,
+            quota_project_id: None,
+        }
+    }
+
+    pub fn quota_project_id(mut self, project: impl Into<String>) -> Self {
+        self.quota_project_id = Some(project.into());
+        self
+    }
+}
+
+impl TokenProvider for StaticTokenProvider {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        Ok(self.token.clone())
+    }
+
+    fn quota_project_id(&self) -> Option<&str> {
+        self.quota_project_id.as_deref()
+    }
+}
+
+/// A provider whose responses are scripted by the test, for exercising
+/// refresh and error handling.
+///
+/// Queued responses are returned in order, one per `get_token` call. Once
+/// the queue is empty the current token is returned, or an error if none
+/// was set.
+#[derive(Debug, Default)]
+pub struct FakeTokenProvider {
+    responses: Mutex<VecDeque<Result<AccessToken, Error>>>,
+    token: Mutex<Option<AccessToken>>,
+    calls: AtomicUsize,
+    invalidations: AtomicUsize,
+    quota_project_id: Option<String>,
+}
+
+impl FakeTokenProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_token(token: AccessToken) -> Self {
+        let provider = Self::new();
+        provider.set_token(token);
+        provider
+    }
+
+    pub fn quota_project_id(mut self, project: impl Into<String>) -> Self {
+        self.quota_project_id = Some(project.into());
+        self
+    }
+
+    /// Replaces the token returned once the queue is empty.
+    pub fn set_token(&self, token: AccessToken) {
+        *self.token.lock().unwrap() = Some(token);
+    }
+
+    pub fn push_token(&self, token: AccessToken) {
+        self.responses.lock().unwrap().push_back(Ok(token));
+    }
+
+    /// Makes the next unanswered `get_token` call fail with `err`.
+    pub fn push_error(&self, err: Error) {
+        self.responses.lock().unwrap().push_back(Err(err));
+    }
+
+    /// How many times `get_token` has been called.
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    /// How many times `invalidate` has been called.
+    pub fn invalidations(&self) -> usize {
+        self.invalidations.load(Ordering::SeqCst)
+    }
+}
+
+impl TokenProvider for FakeTokenProvider {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        if let Some(response) = self.responses.lock().unwrap().pop_front() {
+            return response;
+        }
+        self.token
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| Error::InvalidCredentials("no fake token configured".to_owned()))
+    }
+
+    fn invalidate(&self) {
+        self.invalidations.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn quota_project_id(&self) -> Option<&str> {
+        self.quota_project_id.as_deref()
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{default_http_client, parse_response, Error};
+use reqwest::header::HeaderMap;
+use reqwest::Client as HTTPClient;
+use serde::Deserialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TOKEN_INFO_URL: &str = "https://oauth2.googleapis.com/tokeninfo";
+
+// tokeninfo returns its numbers as strings.
+#[derive(Deserialize, Debug)]
+struct TokenInfoResponse {
+    aud: Option<String>,
+    azp: Option<String>,
+    sub: Option<String>,
+    email: Option<String>,
+    email_verified: Option<String>,
+    scope: Option<String>,
+    exp: Option<String>,
+}
+
+/// What Google knows about an access token.
+#[derive(Debug, Clone)]
+pub struct TokenInfo {
+    pub audience: Option<String>,
+    pub authorized_party: Option<String>,
+    pub subject: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub scopes: Vec<String>,
+    pub expires: Option<SystemTime>,
+}
+
+/// Looks up an access token with the tokeninfo endpoint, e.g. to check which
+/// scopes it was granted. Fails with an `OAuth` error if Google rejects it.
+pub fn token_info(access_token: &str) -> Result<TokenInfo, Error> {
+    fetch(&default_http_client(), HeaderMap::new(), access_token)
+}
+
+pub(crate) fn fetch(
+    http: &HTTPClient,
+    headers: HeaderMap,
+    access_token: &str,
+) -> Result<TokenInfo, Error> {
+    // Sent in the body so the token stays out of URLs that proxies and
+    // servers tend to log.
+    let response = http
+        .post(TOKEN_INFO_URL)
+        .headers(headers)
+        .form(&[("access_token", access_token)])
+        .send()?;
+    let info = parse_response::<TokenInfoResponse>(response)?;
+    Ok(TokenInfo {
+        audience: info.aud,
+        authorized_party: info.azp,
+        subject: info.sub,
+        email: info.email,
+        email_verified: info.email_verified.as_deref() == Some("true"),
+        scopes: info
+            .scope
+            .as_deref()
+            .unwrap_or("")
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect(),
+        expires: info
+            .exp
+            .and_then(|exp| exp.parse().ok())
+            .map(|exp| UNIX_EPOCH + Duration::from_secs(exp)),
+    })
+}
@@ -0,0 +1,86 @@
+use crate::file_cache::{key_path, write_private};
+use crate::{Error, UserCredentials};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Persists user credentials, and with them their refresh tokens, so that
+/// the installed app and device flows only need to run once.
+pub trait TokenStorage: Send + Sync {
+    fn load(&self, key: &str) -> Result<Option<UserCredentials>, Error>;
+
+    fn store(&self, key: &str, credentials: &UserCredentials) -> Result<(), Error>;
+
+    /// Removes the credentials for `key`, if there are any.
+    fn delete(&self, key: &str) -> Result<(), Error>;
+}
+
+/// A [`TokenStorage`] keeping each key's credentials in a plaintext JSON
+/// file that only the current user can read.
+pub struct FileTokenStorage {
+    dir: PathBuf,
+}
+
+impl FileTokenStorage {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileTokenStorage { dir: dir.into() }
+    }
+
+    /// Uses `$XDG_CONFIG_HOME/google-token-provider`, falling back to
+    /// `~/.config/google-token-provider`.
+    pub fn default_dir() -> Option<PathBuf> {
+        let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_dir.join("google-token-provider"))
+    }
+}
+
+impl TokenStorage for FileTokenStorage {
+    fn load(&self, key: &str) -> Result<Option<UserCredentials>, Error> {
+        match fs::read_to_string(key_path(&self.dir, key)) {
+            Ok(json) => Ok(Some(UserCredentials::from_json_str(&json)?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn store(&self, key: &str, credentials: &UserCredentials) -> Result<(), Error> {
+        let json = serde_json::to_vec(credentials)?;
+        Ok(write_private(&self.dir, &key_path(&self.dir, key), &json)?)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        match fs::remove_file(key_path(&self.dir, key)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Where a flow keeps the credentials it obtains, and under which key.
+pub(crate) type StorageSlot = Option<(Arc<dyn TokenStorage>, String)>;
+
+/// Returns the stored credentials, or runs `authorize` and stores its
+/// result.
+pub(crate) fn load_or_authorize(
+    slot: &StorageSlot,
+    authorize: impl FnOnce() -> Result<UserCredentials, Error>,
+) -> Result<UserCredentials, Error> {
+    let (storage, key) = match slot {
+        Some(slot) => slot,
+        None => return authorize(),
+    };
+    if let Some(credentials) = storage.load(key)? {
+        return Ok(credentials);
+    }
+    let credentials = authorize()?;
+    // The user has already consented, so the credentials are still usable.
+    if let Err(err) = storage.store(key, &credentials) {
+        log_warn!("failed to store credentials for {}: {}", key, err);
+    }
+    Ok(credentials)
+}
@@ -0,0 +1,172 @@
+use crate::cache::CachedToken;
+#[cfg(feature = "hyper")]
+use crate::encoding::percent_encode;
+use crate::{
+    collect_scopes, create_jwt, default_headers, retry_after, AccessToken, Claims, Credentials,
+    Error, OAuthErrorResponse, TokenProvider, TokenResponse, GRANT_TYPE,
+};
+use http::header::HeaderMap;
+use http::StatusCode;
+use serde::de::DeserializeOwned;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Sends token endpoint requests, so any HTTP client (or an in-process mock)
+/// can back the crate.
+pub trait HttpTransport: Send + Sync {
+    /// POSTs `params` as an `application/x-www-form-urlencoded` body.
+    /// Transport failures can be reported as `Error::Io`.
+    fn post_form(
+        &self,
+        url: &str,
+        headers: &HeaderMap,
+        params: &[(&str, &str)],
+    ) -> Result<HttpResponse, Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    /// The address the response came from, for audit logs.
+    pub remote_addr: Option<SocketAddr>,
+}
+
+impl HttpResponse {
+    /// Parses a successful response body, or turns an error response into
+    /// an OAuth or status error.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        if self.status.is_success() {
+            return Ok(serde_json::from_slice(&self.body)?);
+        }
+        Err(
+            match serde_json::from_slice::<OAuthErrorResponse>(&self.body) {
+                Ok(response) => Error::from(response),
+                Err(_) => Error::Status {
+                    status: self.status,
+                    retry_after: retry_after(&self.headers),
+                },
+            },
+        )
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl HttpTransport for reqwest::Client {
+    fn post_form(
+        &self,
+        url: &str,
+        headers: &HeaderMap,
+        params: &[(&str, &str)],
+    ) -> Result<HttpResponse, Error> {
+        let mut response = self
+            .post(url)
+            .headers(headers.clone())
+            .form(params)
+            .send()?;
+        let mut body = Vec::new();
+        response.copy_to(&mut body)?;
+        Ok(HttpResponse {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body,
+            remote_addr: response.remote_addr(),
+        })
+    }
+}
+
+#[cfg(feature = "hyper")]
+pub(crate) fn form_body(params: &[(&str, &str)]) -> String {
+    params
+        .iter()
+        .map(|(name, value)| format!("{}={}", percent_encode(name), percent_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// A service account client that sends its token requests through an
+/// `HttpTransport`, for builds without reqwest.
+pub struct TransportClient {
+    credentials: Credentials,
+    scopes: String,
+    token_url: String,
+    subject: Option<String>,
+    transport: Arc<dyn HttpTransport>,
+    access_token: CachedToken,
+}
+
+impl TransportClient {
+    pub fn new(
+        credentials: Credentials,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+        transport: Arc<dyn HttpTransport>,
+    ) -> TransportClient {
+        TransportClient {
+            token_url: credentials.token_uri().to_owned(),
+            credentials,
+            scopes: collect_scopes(scopes).join(" "),
+            subject: None,
+            transport,
+            access_token: CachedToken::default(),
+        }
+    }
+
+    pub fn token_url(mut self, token_url: impl Into<String>) -> Self {
+        self.token_url = token_url.into();
+        self
+    }
+
+    pub fn subject(mut self, subject: impl Into<String>) -> Self {
+        self.subject = Some(subject.into());
+        self
+    }
+
+    pub fn refresh_margin(mut self, margin: Duration) -> Self {
+        self.access_token = CachedToken::new(margin);
+        self
+    }
+
+    pub fn get_token(&self) -> Result<AccessToken, Error> {
+        self.access_token.get_or_fetch(|| self.fetch_token())
+    }
+
+    pub fn invalidate(&self) {
+        self.access_token.invalidate();
+    }
+
+    fn fetch_token(&self) -> Result<AccessToken, Error> {
+        let claims = Claims {
+            scope: Some(self.scopes.clone()),
+            sub: self.subject.clone(),
+            ..Claims::new(&self.credentials, &self.token_url)?
+        };
+        let assertion = create_jwt(&self.credentials, &claims)?;
+        let response = self.transport.post_form(
+            &self.token_url,
+            &default_headers(),
+            &[("grant_type", GRANT_TYPE), ("assertion", &assertion)],
+        )?;
+        let response = response.json::<TokenResponse>()?;
+        Ok(AccessToken::from_response(response, SystemTime::now()))
+    }
+}
+
+impl TokenProvider for TransportClient {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        TransportClient::get_token(self)
+    }
+
+    fn invalidate(&self) {
+        TransportClient::invalidate(self)
+    }
+
+    fn quota_project_id(&self) -> Option<&str> {
+        self.credentials.quota_project_id()
+    }
+
+    fn universe_domain(&self) -> &str {
+        self.credentials.universe_domain()
+    }
+}
@@ -0,0 +1,166 @@
+use crate::cache::CachedToken;
+use crate::credentials;
+use crate::revoke;
+use crate::{
+    default_headers, default_http_client, parse_response, AccessToken, Error, RetryPolicy,
+    TokenProvider, TokenResponse,
+};
+use reqwest::Client as HTTPClient;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+pub(crate) const USER_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserCredentials {
+    client_id: String,
+    client_secret: String,
+    refresh_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quota_project_id: Option<String>,
+}
+
+impl UserCredentials {
+    pub fn new(
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        refresh_token: impl Into<String>,
+    ) -> Self {
+        UserCredentials {
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            refresh_token: refresh_token.into(),
+            quota_project_id: None,
+        }
+    }
+
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let json = fs::read_to_string(path)?;
+        Self::from_json_str(&json)
+    }
+
+    pub fn from_json_str(json: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Loads the user credentials of the active gcloud account.
+    pub fn from_gcloud() -> Result<Self, Error> {
+        match credentials::gcloud_credentials_file() {
+            Some(path) => Self::from_json_file(path),
+            None => Err(Error::CredentialsNotFound),
+        }
+    }
+
+    /// Revokes the refresh token, and with it the user's grant to the client.
+    pub fn revoke(&self) -> Result<(), Error> {
+        revoke::revoke(&default_http_client(), &self.refresh_token)
+    }
+
+    pub fn quota_project_id(&self) -> Option<&str> {
+        self.quota_project_id.as_deref()
+    }
+}
+
+impl fmt::Debug for UserCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UserCredentials")
+            .field("client_id", &self.client_id)
+            .field("client_secret", &"<redacted>")
+            .field("refresh_token", &"<redacted>")
+            .field("quota_project_id", &self.quota_project_id)
+            .finish()
+    }
+}
+
+pub struct UserClient {
+    credentials: UserCredentials,
+    token_url: String,
+    retry: RetryPolicy,
+    http: HTTPClient,
+    access_token: CachedToken,
+}
+
+impl UserClient {
+    pub fn new(credentials: UserCredentials) -> UserClient {
+        UserClient {
+            credentials,
+            token_url: USER_TOKEN_URL.to_owned(),
+            retry: RetryPolicy::default(),
+            http: default_http_client(),
+            access_token: CachedToken::default(),
+        }
+    }
+
+    pub fn token_url(mut self, token_url: impl Into<String>) -> Self {
+        self.token_url = token_url.into();
+        self
+    }
+
+    pub fn http_client(mut self, http: HTTPClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn refresh_margin(mut self, margin: Duration) -> Self {
+        self.access_token = CachedToken::new(margin);
+        self
+    }
+
+    pub fn get_token(&self) -> Result<AccessToken, Error> {
+        self.access_token.get_or_fetch(|| self.fetch_token())
+    }
+
+    pub fn force_refresh(&self) -> Result<AccessToken, Error> {
+        self.access_token.refresh(|| self.fetch_token())
+    }
+
+    pub fn invalidate(&self) {
+        self.access_token.invalidate();
+    }
+
+    fn fetch_token(&self) -> Result<AccessToken, Error> {
+        let mut params = HashMap::new();
+        params.insert("grant_type", "refresh_token");
+        params.insert("client_id", &self.credentials.client_id);
+        params.insert("client_secret", &self.credentials.client_secret);
+        params.insert("refresh_token", &self.credentials.refresh_token);
+
+        self.retry.retry(|| {
+            let response = self
+                .http
+                .post(&self.token_url)
+                .headers(default_headers())
+                .form(&params)
+                .send()?;
+            let token = parse_response::<TokenResponse>(response)?;
+            Ok(AccessToken::from(token))
+        })
+    }
+}
+
+impl TokenProvider for UserClient {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        UserClient::get_token(self)
+    }
+
+    fn invalidate(&self) {
+        UserClient::invalidate(self)
+    }
+
+    fn force_refresh(&self) -> Result<AccessToken, Error> {
+        UserClient::force_refresh(self)
+    }
+
+    fn quota_project_id(&self) -> Option<&str> {
+        self.credentials.quota_project_id()
+    }
+}
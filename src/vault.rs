@@ -0,0 +1,362 @@
+use crate::cache::CachedToken;
+use crate::{
+    default_headers, default_http_client, parse_response, AccessToken, Client, Credentials, Error,
+    TokenProvider,
+};
+use reqwest::Client as HTTPClient;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::cmp;
+use std::env;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const VAULT_ADDR_ENV_VAR: &str = "VAULT_ADDR";
+const VAULT_TOKEN_ENV_VAR: &str = "VAULT_TOKEN";
+const VAULT_NAMESPACE_ENV_VAR: &str = "VAULT_NAMESPACE";
+const DEFAULT_MOUNT: &str = "gcp";
+// Keys are replaced this long before their lease runs out.
+const KEY_RENEW_MARGIN: Duration = Duration::from_secs(5 * 60);
+// How long to wait before asking Vault again after a failed renewal.
+const KEY_RENEW_RETRY: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize, Debug)]
+struct VaultResponse<T> {
+    data: T,
+    #[serde(default)]
+    lease_duration: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct VaultToken {
+    token: String,
+    expires_at_seconds: Option<u64>,
+    token_ttl: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct VaultKey {
+    private_key_data: String,
+}
+
+/// Reads credentials from Vault's Google Cloud secrets engine, for
+/// deployments that keep all cloud credentials in Vault.
+pub struct VaultClient {
+    addr: String,
+    vault_token: String,
+    namespace: Option<String>,
+    mount: String,
+    account: String,
+    http: HTTPClient,
+    access_token: CachedToken,
+}
+
+impl VaultClient {
+    /// `account` is the roleset, static account or impersonated account to
+    /// read, e.g. `roleset/my-roleset` or `static-account/ci`.
+    pub fn new(
+        addr: impl Into<String>,
+        vault_token: impl Into<String>,
+        account: impl Into<String>,
+    ) -> Self {
+        VaultClient {
+            addr: addr.into().trim_end_matches('/').to_owned(),
+            vault_token: vault_token.into(),
+            namespace: None,
+            mount: DEFAULT_MOUNT.to_owned(),
+            account: account.into(),
+            http: default_http_client(),
+            access_token: CachedToken::default(),
+        }
+    }
+
+    /// Uses `VAULT_ADDR`, `VAULT_TOKEN` and, if set, `VAULT_NAMESPACE`.
+    pub fn from_env(account: impl Into<String>) -> Result<Self, Error> {
+        let var = |name| {
+            env::var(name).map_err(|_| Error::InvalidCredentials(format!("{} is not set", name)))
+        };
+        let client = VaultClient::new(var(VAULT_ADDR_ENV_VAR)?, var(VAULT_TOKEN_ENV_VAR)?, account);
+        Ok(match env::var(VAULT_NAMESPACE_ENV_VAR) {
+            Ok(namespace) => client.namespace(namespace),
+            Err(_) => client,
+        })
+    }
+
+    /// The path the secrets engine is mounted at. Defaults to `gcp`.
+    pub fn mount(mut self, mount: impl Into<String>) -> Self {
+        self.mount = mount.into().trim_matches('/').to_owned();
+        self
+    }
+
+    /// The Vault Enterprise namespace to send requests to.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    pub fn http_client(mut self, http: HTTPClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// An OAuth access token for the account. The account must have been
+    /// configured with the `access_token` secret type.
+    pub fn get_token(&self) -> Result<AccessToken, Error> {
+        self.access_token.get_or_fetch(|| self.fetch_token())
+    }
+
+    pub fn invalidate(&self) {
+        self.access_token.invalidate();
+    }
+
+    /// A newly created service account key, along with how long Vault keeps
+    /// it before revoking it. The account must have been configured with
+    /// the `service_account_key` secret type.
+    pub fn service_account_key(&self) -> Result<(Credentials, Duration), Error> {
+        let response = self.read::<VaultKey>("key")?;
+        let json = base64::decode(&response.data.private_key_data)
+            .ok()
+            .and_then(|json| String::from_utf8(json).ok())
+            .ok_or_else(|| {
+                Error::InvalidCredentials("Vault returned an invalid key file".to_owned())
+            })?;
+        Ok((
+            Credentials::from_json_str(&json)?,
+            Duration::from_secs(response.lease_duration),
+        ))
+    }
+
+    fn fetch_token(&self) -> Result<AccessToken, Error> {
+        let token = self.read::<VaultToken>("token")?.data;
+        let expires = match (token.token_ttl, token.expires_at_seconds) {
+            (Some(ttl), _) => SystemTime::now() + Duration::from_secs(ttl),
+            (None, Some(expires_at)) => UNIX_EPOCH + Duration::from_secs(expires_at),
+            (None, None) => {
+                return Err(Error::InvalidCredentials(
+                    "Vault returned a token without an expiry".to_owned(),
+                ))
+            }
+        };
+        Ok(AccessToken::new(token.token, expires))
+    }
+
+    fn read<T: DeserializeOwned>(&self, secret: &str) -> Result<VaultResponse<T>, Error> {
+        let mut request = self
+            .http
+            .get(&format!(
+                "{}/v1/{}/{}/{}",
+                self.addr, self.mount, self.account, secret
+            ))
+            .headers(default_headers())
+            .header("X-Vault-Token", self.vault_token.as_str());
+        if let Some(namespace) = &self.namespace {
+            request = request.header("X-Vault-Namespace", namespace.as_str());
+        }
+        parse_response(request.send()?)
+    }
+}
+
+impl TokenProvider for VaultClient {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        VaultClient::get_token(self)
+    }
+
+    fn invalidate(&self) {
+        VaultClient::invalidate(self)
+    }
+}
+
+impl fmt::Debug for VaultClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VaultClient")
+            .field("addr", &self.addr)
+            .field("vault_token", &"<redacted>")
+            .field("namespace", &self.namespace)
+            .field("mount", &self.mount)
+            .field("account", &self.account)
+            .finish()
+    }
+}
+
+/// A service account client whose short-lived keys come from Vault,
+/// replaced with a new key shortly before each lease expires.
+pub struct VaultKeyClient {
+    vault: VaultClient,
+    scopes: Vec<String>,
+    state: Mutex<KeyState>,
+}
+
+/// A client for the current key, when to replace it and when its lease
+/// ends. Keys without a lease are kept.
+struct KeyState {
+    client: Client,
+    renew_at: Option<Instant>,
+    expires_at: Option<Instant>,
+}
+
+impl VaultKeyClient {
+    /// Fetches the first key right away, failing if Vault cannot issue one.
+    pub fn new(
+        vault: VaultClient,
+        scopes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Self, Error> {
+        let scopes: Vec<String> = scopes.into_iter().map(|s| s.as_ref().to_owned()).collect();
+        let state = Self::issue(&vault, &scopes)?;
+        Ok(VaultKeyClient {
+            vault,
+            scopes,
+            state: Mutex::new(state),
+        })
+    }
+
+    pub fn get_token(&self) -> Result<AccessToken, Error> {
+        self.client()?.get_token()
+    }
+
+    pub fn invalidate(&self) {
+        self.state.lock().unwrap().client.invalidate();
+    }
+
+    fn client(&self) -> Result<Client, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        if matches!(state.renew_at, Some(renew_at) if now >= renew_at) {
+            match Self::issue(&self.vault, &self.scopes) {
+                Ok(renewed) => *state = renewed,
+                Err(err) => match state.expires_at {
+                    // Keep using the current key until its lease ends.
+                    Some(expires_at) if now < expires_at => {
+                        log_warn!("failed to renew the Vault service account key: {}", err);
+                        state.renew_at = Some(cmp::min(now + KEY_RENEW_RETRY, expires_at));
+                    }
+                    _ => return Err(err),
+                },
+            }
+        }
+        Ok(state.client.clone())
+    }
+
+    fn issue(vault: &VaultClient, scopes: &[String]) -> Result<KeyState, Error> {
+        let (credentials, lease) = vault.service_account_key()?;
+        let now = Instant::now();
+        let (renew_at, expires_at) = match lease.as_secs() {
+            0 => (None, None),
+            _ => (
+                Some(now + lease.checked_sub(KEY_RENEW_MARGIN).unwrap_or(lease / 2)),
+                Some(now + lease),
+            ),
+        };
+        Ok(KeyState {
+            client: Client::new(credentials, scopes),
+            renew_at,
+            expires_at,
+        })
+    }
+}
+
+impl TokenProvider for VaultKeyClient {
+    fn get_token(&self) -> Result<AccessToken, Error> {
+        VaultKeyClient::get_token(self)
+    }
+
+    fn invalidate(&self) {
+        VaultKeyClient::invalidate(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Serves a Vault key read and a token endpoint for the key's
+    /// assertions. Key reads fail while `vault_down` is set.
+    fn serve() -> (String, Arc<AtomicBool>, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let key_file = json!({
+            "private_key": include_str!("../testdata/rsa_pkcs1.pem"),
+            "client_email": "vault@example-project.iam.gserviceaccount.com",
+            "token_uri": format!("{}/token", addr),
+        });
+        let key = json!({
+            "data": { "private_key_data": base64::encode(&key_file.to_string()) },
+            "lease_duration": 3600,
+        })
+        .to_string();
+        let vault_down = Arc::new(AtomicBool::new(false));
+        let key_reads = Arc::new(AtomicUsize::new(0));
+        let (down, reads) = (Arc::clone(&vault_down), Arc::clone(&key_reads));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let (mut line, mut length) = (String::new(), 0);
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    line.clear();
+                }
+                reader.read_exact(&mut vec![0; length]).unwrap();
+
+                let (status, body) = if request_line.contains("/key ") {
+                    reads.fetch_add(1, Ordering::SeqCst);
+                    if down.load(Ordering::SeqCst) {
+                        ("503 Service Unavailable", "{}".to_owned())
+                    } else {
+                        ("200 OK", key.clone())
+                    }
+                } else {
+                    let token = json!({ "access_token": "token", "token_type": "Bearer", "expires_in": 3600 });
+                    ("200 OK", token.to_string())
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-type: application/json\r\n\
+                     content-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (addr, vault_down, key_reads)
+    }
+
+    #[test]
+    fn keeps_the_current_key_while_renewal_fails() {
+        let (addr, vault_down, key_reads) = serve();
+        let vault = VaultClient::new(addr, "vault-token", "roleset/r");
+        let client = VaultKeyClient::new(vault, ["scope"]).unwrap();
+        assert_eq!(key_reads.load(Ordering::SeqCst), 1);
+
+        vault_down.store(true, Ordering::SeqCst);
+        client.state.lock().unwrap().renew_at = Some(Instant::now());
+        for _ in 0..3 {
+            assert_eq!(client.get_token().unwrap().value, "token");
+        }
+        // One failed renewal, then none until the retry is due.
+        assert_eq!(key_reads.load(Ordering::SeqCst), 2);
+
+        // Once the lease is over, the failure surfaces.
+        {
+            let mut state = client.state.lock().unwrap();
+            state.renew_at = Some(Instant::now());
+            state.expires_at = Some(Instant::now());
+        }
+        assert!(client.get_token().is_err());
+
+        vault_down.store(false, Ordering::SeqCst);
+        assert_eq!(client.get_token().unwrap().value, "token");
+        assert_eq!(key_reads.load(Ordering::SeqCst), 4);
+    }
+}
@@ -0,0 +1,603 @@
+use crate::key;
+use crate::{default_headers, default_http_client, parse_response, Clock, Error, SystemClock};
+use reqwest::header::CACHE_CONTROL;
+use reqwest::Client as HTTPClient;
+use ring::signature::{self, primitive, ECDSA_P256_SHA256_FIXED, RSA_PKCS1_2048_8192_SHA256};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+const GOOGLE_CERTS_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+const GOOGLE_ISSUERS: &[&str] = &["accounts.google.com", "https://accounts.google.com"];
+const IAP_CERTS_URL: &str = "https://www.gstatic.com/iap/verify/public_key-jwk";
+const IAP_ISSUER: &str = "https://cloud.google.com/iap";
+const FIREBASE_CERTS_URL: &str =
+    "https://www.googleapis.com/robot/v1/metadata/x509/securetoken@system.gserviceaccount.com";
+const FIREBASE_ISSUER_PREFIX: &str = "https://securetoken.google.com/";
+const DEFAULT_LEEWAY: Duration = Duration::from_secs(60);
+const DEFAULT_KEYS_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+// Unknown key IDs refetch the keys at most this often.
+const MIN_KEYS_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize, Debug)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Jwk {
+    kid: String,
+    kty: String,
+    n: Option<String>,
+    e: Option<String>,
+    crv: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+/// The header Identity-Aware Proxy sends its signed assertion in.
+pub const IAP_JWT_HEADER: &str = "x-goog-iap-jwt-assertion";
+
+/// The claims of a verified Google ID token or IAP assertion.
+#[derive(Deserialize, Debug, Clone)]
+pub struct VerifiedClaims {
+    pub iss: String,
+    pub aud: String,
+    pub sub: String,
+    pub exp: u64,
+    pub iat: u64,
+    pub azp: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    /// The G Suite domain of the user, if any.
+    pub hd: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Verifies Google signed ID tokens presented to a service, such as those
+/// sent by Cloud Scheduler, Pub/Sub push subscriptions or Cloud Tasks.
+pub struct IdTokenVerifier {
+    audiences: Vec<String>,
+    issuers: Vec<String>,
+    keys: KeySet,
+    leeway: Duration,
+    clock: Arc<dyn Clock>,
+    http: HTTPClient,
+}
+
+/// A public key endpoint, cached as long as its `Cache-Control` allows.
+struct KeySet {
+    url: String,
+    format: KeyFormat,
+    cached: Mutex<CachedKeys>,
+    // Held while fetching, so callers needing keys wait for one request
+    // rather than each sending their own.
+    fetch: Mutex<()>,
+}
+
+#[derive(Default)]
+struct CachedKeys {
+    keys: Vec<Jwk>,
+    expires: Option<Instant>,
+    fetched: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum KeyFormat {
+    Jwks,
+    /// A JSON object of key IDs to PEM encoded X.509 certificates.
+    X509,
+}
+
+/// The claims of a verified Firebase Auth ID token.
+#[derive(Deserialize, Debug, Clone)]
+pub struct FirebaseClaims {
+    pub iss: String,
+    pub aud: String,
+    pub sub: String,
+    pub exp: u64,
+    pub iat: u64,
+    pub auth_time: u64,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub phone_number: Option<String>,
+    pub name: Option<String>,
+    pub picture: Option<String>,
+    pub firebase: FirebaseInfo,
+    /// Custom claims set through the Admin SDK, among others.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FirebaseInfo {
+    pub sign_in_provider: String,
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub identities: Map<String, Value>,
+}
+
+/// Verifies Firebase Auth ID tokens issued for a Firebase project.
+pub struct FirebaseTokenVerifier {
+    verifier: IdTokenVerifier,
+}
+
+impl IdTokenVerifier {
+    /// Accepts tokens whose `aud` is `audience`, typically the URL of the
+    /// receiving service.
+    pub fn new(audience: impl Into<String>) -> Self {
+        IdTokenVerifier {
+            audiences: vec![audience.into()],
+            issuers: GOOGLE_ISSUERS.iter().map(|&iss| iss.to_owned()).collect(),
+            keys: KeySet::new(GOOGLE_CERTS_URL, KeyFormat::Jwks),
+            leeway: DEFAULT_LEEWAY,
+            clock: Arc::new(SystemClock),
+            http: default_http_client(),
+        }
+    }
+
+    /// Verifies Identity-Aware Proxy assertions, sent in the
+    /// `x-goog-iap-jwt-assertion` header. `audience` is
+    /// `/projects/PROJECT_NUMBER/apps/PROJECT_ID` for App Engine, or
+    /// `/projects/PROJECT_NUMBER/global/backendServices/SERVICE_ID` otherwise.
+    pub fn iap(audience: impl Into<String>) -> Self {
+        IdTokenVerifier {
+            issuers: vec![IAP_ISSUER.to_owned()],
+            keys: KeySet::new(IAP_CERTS_URL, KeyFormat::Jwks),
+            ..Self::new(audience)
+        }
+    }
+
+    /// Accepts `audience` in addition to the ones already configured.
+    pub fn audience(mut self, audience: impl Into<String>) -> Self {
+        self.audiences.push(audience.into());
+        self
+    }
+
+    pub fn certs_url(mut self, url: impl Into<String>) -> Self {
+        self.keys = KeySet::new(url, self.keys.format);
+        self
+    }
+
+    /// How far `exp` and `iat` may be off to allow for clock drift.
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn http_client(mut self, http: HTTPClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn verify(&self, token: &str) -> Result<VerifiedClaims, Error> {
+        let claims: VerifiedClaims = self.verify_signature(token)?;
+        self.check_claims(&claims.iss, &claims.aud, claims.exp, claims.iat)?;
+        Ok(claims)
+    }
+
+    fn verify_signature<T: DeserializeOwned>(&self, token: &str) -> Result<T, Error> {
+        let mut parts = token.split('.');
+        let (header, payload, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(payload), Some(signature)) if parts.next().is_none() => {
+                (header, payload, signature)
+            }
+            _ => return Err(invalid_token("not a JWT")),
+        };
+        let header: Header = serde_json::from_slice(&decode(header)?)?;
+        let kid = header
+            .kid
+            .ok_or_else(|| invalid_token("JWT header has no kid"))?;
+        let key = self.keys.get(&self.http, &kid)?;
+
+        let signing_input = &token[..header_and_payload_len(token)];
+        let signature = decode(signature)?;
+        match (header.alg.as_str(), key.kty.as_str()) {
+            ("RS256", "RSA") => verify_rs256(&key, signing_input.as_bytes(), &signature)?,
+            ("ES256", "EC") => verify_es256(&key, signing_input.as_bytes(), &signature)?,
+            (alg, _) => {
+                return Err(invalid_token(format!(
+                    "unexpected algorithm {} for key {}",
+                    alg, kid
+                )))
+            }
+        }
+        Ok(serde_json::from_slice(&decode(payload)?)?)
+    }
+
+    fn check_claims(&self, iss: &str, aud: &str, exp: u64, iat: u64) -> Result<(), Error> {
+        if !self.issuers.iter().any(|issuer| issuer == iss) {
+            return Err(invalid_token(format!("unexpected issuer {}", iss)));
+        }
+        if !self.audiences.iter().any(|audience| audience == aud) {
+            return Err(invalid_token(format!("unexpected audience {}", aud)));
+        }
+        let now = self.clock.now().duration_since(UNIX_EPOCH)?;
+        if Duration::from_secs(exp) + self.leeway <= now {
+            return Err(invalid_token("token has expired"));
+        }
+        if Duration::from_secs(iat) > now + self.leeway {
+            return Err(invalid_token("token was issued in the future"));
+        }
+        Ok(())
+    }
+}
+
+impl FirebaseTokenVerifier {
+    pub fn new(project_id: impl Into<String>) -> Self {
+        let project_id = project_id.into();
+        let verifier = IdTokenVerifier {
+            issuers: vec![format!("{}{}", FIREBASE_ISSUER_PREFIX, project_id)],
+            keys: KeySet::new(FIREBASE_CERTS_URL, KeyFormat::X509),
+            ..IdTokenVerifier::new(project_id)
+        };
+        FirebaseTokenVerifier { verifier }
+    }
+
+    pub fn certs_url(self, url: impl Into<String>) -> Self {
+        FirebaseTokenVerifier {
+            verifier: self.verifier.certs_url(url),
+        }
+    }
+
+    pub fn leeway(self, leeway: Duration) -> Self {
+        FirebaseTokenVerifier {
+            verifier: self.verifier.leeway(leeway),
+        }
+    }
+
+    pub fn clock(self, clock: Arc<dyn Clock>) -> Self {
+        FirebaseTokenVerifier {
+            verifier: self.verifier.clock(clock),
+        }
+    }
+
+    pub fn http_client(self, http: HTTPClient) -> Self {
+        FirebaseTokenVerifier {
+            verifier: self.verifier.http_client(http),
+        }
+    }
+
+    pub fn verify(&self, token: &str) -> Result<FirebaseClaims, Error> {
+        let verifier = &self.verifier;
+        let claims: FirebaseClaims = verifier.verify_signature(token)?;
+        verifier.check_claims(&claims.iss, &claims.aud, claims.exp, claims.iat)?;
+        if claims.sub.is_empty() {
+            return Err(invalid_token("token has an empty subject"));
+        }
+        let now = verifier.clock.now().duration_since(UNIX_EPOCH)?;
+        if Duration::from_secs(claims.auth_time) > now + verifier.leeway {
+            return Err(invalid_token("token was authenticated in the future"));
+        }
+        Ok(claims)
+    }
+}
+
+impl KeySet {
+    fn new(url: impl Into<String>, format: KeyFormat) -> Self {
+        KeySet {
+            url: url.into(),
+            format,
+            cached: Mutex::default(),
+            fetch: Mutex::new(()),
+        }
+    }
+
+    /// Refetches the keys when they have expired or `kid` is unknown, since
+    /// Google rotates its keys. Unknown key IDs only trigger a fetch once
+    /// per `MIN_KEYS_REFETCH_INTERVAL`, so forged tokens cannot force a
+    /// request each.
+    fn get(&self, http: &HTTPClient, kid: &str) -> Result<Jwk, Error> {
+        if let Some(key) = self.lookup(kid)? {
+            return Ok(key);
+        }
+        let _fetch = self.fetch.lock().unwrap();
+        // Another caller may have fetched the keys in the meantime.
+        if let Some(key) = self.lookup(kid)? {
+            return Ok(key);
+        }
+        self.cached.lock().unwrap().fetched = Some(Instant::now());
+        let (keys, max_age) = self.fetch_keys(http)?;
+        let key = keys.iter().find(|key| key.kid == kid).cloned();
+        let mut cached = self.cached.lock().unwrap();
+        cached.keys = keys;
+        cached.expires = Some(Instant::now() + max_age);
+        key.ok_or_else(|| invalid_token(format!("unknown signing key {}", kid)))
+    }
+
+    /// The cached key for `kid`, `None` if the keys should be fetched, or an
+    /// error if `kid` is unknown and the keys were fetched too recently.
+    fn lookup(&self, kid: &str) -> Result<Option<Jwk>, Error> {
+        let cached = self.cached.lock().unwrap();
+        if !matches!(cached.expires, Some(expires) if Instant::now() < expires) {
+            return Ok(None);
+        }
+        if let Some(key) = cached.keys.iter().find(|key| key.kid == kid) {
+            return Ok(Some(key.clone()));
+        }
+        match cached.fetched {
+            Some(fetched) if fetched.elapsed() < MIN_KEYS_REFETCH_INTERVAL => {
+                Err(invalid_token(format!("unknown signing key {}", kid)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn fetch_keys(&self, http: &HTTPClient) -> Result<(Vec<Jwk>, Duration), Error> {
+        let response = http.get(&self.url).headers(default_headers()).send()?;
+        let max_age = response
+            .headers()
+            .get(CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok())
+            .and_then(max_age)
+            .unwrap_or(DEFAULT_KEYS_MAX_AGE);
+        let keys = match self.format {
+            KeyFormat::Jwks => parse_response::<JwkSet>(response)?.keys,
+            KeyFormat::X509 => parse_response::<HashMap<String, String>>(response)?
+                .into_iter()
+                .map(|(kid, cert)| jwk_from_cert(kid, &cert))
+                .collect::<Result<_, _>>()?,
+        };
+        Ok((keys, max_age))
+    }
+}
+
+fn jwk_from_cert(kid: String, cert: &str) -> Result<Jwk, Error> {
+    let (n, e) = key::rsa_public_key_from_cert(cert)?;
+    Ok(Jwk {
+        kid,
+        kty: "RSA".to_owned(),
+        n: Some(base64::encode_config(&n, base64::URL_SAFE_NO_PAD)),
+        e: Some(base64::encode_config(&e, base64::URL_SAFE_NO_PAD)),
+        crv: None,
+        x: None,
+        y: None,
+    })
+}
+
+fn max_age(cache_control: &str) -> Option<Duration> {
+    cache_control
+        .split(',')
+        .filter_map(|directive| directive.trim().strip_prefix("max-age="))
+        .next()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+}
+
+fn verify_rs256(key: &Jwk, message: &[u8], signature: &[u8]) -> Result<(), Error> {
+    let (n, e) = match (&key.n, &key.e) {
+        (Some(n), Some(e)) => (decode(n)?, decode(e)?),
+        _ => return Err(invalid_token(format!("RSA key {} is incomplete", key.kid))),
+    };
+    primitive::verify_rsa(
+        &RSA_PKCS1_2048_8192_SHA256,
+        (
+            untrusted::Input::from(strip_leading_zeros(&n)),
+            untrusted::Input::from(strip_leading_zeros(&e)),
+        ),
+        untrusted::Input::from(message),
+        untrusted::Input::from(signature),
+    )
+    .map_err(|_| invalid_token("invalid signature"))
+}
+
+fn verify_es256(key: &Jwk, message: &[u8], signature: &[u8]) -> Result<(), Error> {
+    let (x, y) = match (key.crv.as_deref(), &key.x, &key.y) {
+        (Some("P-256"), Some(x), Some(y)) => (decode(x)?, decode(y)?),
+        _ => {
+            return Err(invalid_token(format!(
+                "EC key {} is not a P-256 key",
+                key.kid
+            )))
+        }
+    };
+    // An uncompressed SEC1 point.
+    let mut point = vec![0x04];
+    point.extend_from_slice(&x);
+    point.extend_from_slice(&y);
+    signature::verify(
+        &ECDSA_P256_SHA256_FIXED,
+        untrusted::Input::from(&point),
+        untrusted::Input::from(message),
+        untrusted::Input::from(signature),
+    )
+    .map_err(|_| invalid_token("invalid signature"))
+}
+
+fn strip_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|&byte| byte != 0)
+        .unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn header_and_payload_len(token: &str) -> usize {
+    token.rfind('.').unwrap_or(token.len())
+}
+
+fn decode(part: &str) -> Result<Vec<u8>, Error> {
+    base64::decode_config(part, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| invalid_token("invalid base64 in JWT"))
+}
+
+fn invalid_token(reason: impl Into<String>) -> Error {
+    Error::InvalidToken(reason.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::PrivateKey;
+    use crate::MockClock;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    const JWKS: &str = r#"{"keys": [{"kid": "k1", "kty": "RSA", "n": "AQAB", "e": "AQAB"}]}"#;
+    // The public half of `testdata/rsa_pkcs1.pem`, as key `k1`.
+    const FIXTURE_JWKS: &str = include_str!("../testdata/jwks.json");
+    const NOW: u64 = 1_600_000_000;
+
+    /// Serves `jwks` to every request, returning its URL and a request count.
+    fn serve_jwks(jwks: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/certs", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let count = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                count.fetch_add(1, Ordering::SeqCst);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\
+                     content-length: {}\r\nconnection: close\r\n\r\n{}",
+                    jwks.len(),
+                    jwks
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn token(kid: &str) -> String {
+        let segment = |json: &str| base64::encode_config(json.as_bytes(), base64::URL_SAFE_NO_PAD);
+        format!(
+            "{}.{}.c2ln",
+            segment(&format!(r#"{{"alg":"RS256","kid":"{}"}}"#, kid)),
+            segment("{}")
+        )
+    }
+
+    #[test]
+    fn unknown_kids_do_not_refetch_keys() {
+        let (url, requests) = serve_jwks(JWKS);
+        let verifier = IdTokenVerifier::new("aud").certs_url(url);
+        for kid in &["a", "b", "c"] {
+            let err = verifier.verify(&token(kid)).unwrap_err();
+            assert!(err.to_string().contains("unknown signing key"), "{}", err);
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Known keys are still served from the cache.
+        let err = verifier.verify(&token("k1")).unwrap_err();
+        assert!(err.to_string().contains("invalid signature"), "{}", err);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    fn signed_token(claims: Value) -> String {
+        PrivateKey::from_pem(include_str!("../testdata/rsa_pkcs1.pem"))
+            .unwrap()
+            .sign_jwt(Some("k1"), &claims)
+            .unwrap()
+    }
+
+    fn claims() -> Value {
+        json!({
+            "iss": "https://accounts.google.com",
+            "aud": "https://service.example.com",
+            "sub": "1234",
+            "email": "caller@example.com",
+            "email_verified": true,
+            "iat": NOW - 60,
+            "exp": NOW + 3540,
+        })
+    }
+
+    fn verifier() -> IdTokenVerifier {
+        let (url, _) = serve_jwks(FIXTURE_JWKS);
+        IdTokenVerifier::new("https://service.example.com")
+            .certs_url(url)
+            .clock(Arc::new(MockClock::new(
+                UNIX_EPOCH + Duration::from_secs(NOW),
+            )))
+    }
+
+    #[test]
+    fn verifies_signed_tokens() {
+        let claims = verifier().verify(&signed_token(claims())).unwrap();
+        assert_eq!(claims.sub, "1234");
+        assert_eq!(claims.email.as_deref(), Some("caller@example.com"));
+        assert!(claims.email_verified);
+    }
+
+    #[test]
+    fn rejects_invalid_claims() {
+        let verifier = verifier();
+        let cases = [
+            (
+                "iss",
+                json!("https://evil.example.com"),
+                "unexpected issuer",
+            ),
+            (
+                "aud",
+                json!("https://other.example.com"),
+                "unexpected audience",
+            ),
+            ("exp", json!(NOW - 61), "token has expired"),
+            ("iat", json!(NOW + 61), "issued in the future"),
+        ];
+        for (claim, value, reason) in &cases {
+            let mut claims = claims();
+            claims[*claim] = value.clone();
+            let err = verifier.verify(&signed_token(claims)).unwrap_err();
+            assert!(err.to_string().contains(reason), "{}: {}", claim, err);
+        }
+
+        // Within the leeway.
+        let mut claims = claims();
+        claims["exp"] = json!(NOW - 59);
+        assert!(verifier.verify(&signed_token(claims)).is_ok());
+    }
+
+    #[test]
+    fn rejects_tampered_tokens() {
+        let verifier = verifier();
+        let token = signed_token(claims());
+        let mut parts: Vec<_> = token.split('.').collect();
+        let mut forged = claims();
+        forged["sub"] = json!("5678");
+        let payload = base64::encode_config(&forged.to_string(), base64::URL_SAFE_NO_PAD);
+        parts[1] = &payload;
+        let err = verifier.verify(&parts.join(".")).unwrap_err();
+        assert!(err.to_string().contains("invalid signature"), "{}", err);
+
+        let err = verifier.verify("not.a.jwt.at-all").unwrap_err();
+        assert!(err.to_string().contains("not a JWT"), "{}", err);
+    }
+
+    #[test]
+    fn parses_max_age() {
+        assert_eq!(
+            max_age("public, max-age=19385, must-revalidate"),
+            Some(Duration::from_secs(19385))
+        );
+        assert_eq!(max_age("no-cache"), None);
+    }
+}
@@ -0,0 +1,76 @@
+use google_token_provider::{Client, Credentials, Error, MockResponse, MockTokenServer};
+
+const SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+
+fn credentials() -> Credentials {
+    Credentials::from_pem(
+        include_str!("../testdata/rsa_pkcs1.pem"),
+        "test@example-project.iam.gserviceaccount.com",
+    )
+    .unwrap()
+}
+
+fn client(server: &MockTokenServer) -> Client {
+    Client::builder(credentials(), [SCOPE])
+        .token_url(server.token_url())
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn fetches_tokens_from_the_mock_server() {
+    let server = MockTokenServer::start(credentials()).unwrap();
+    server.respond_with(MockResponse::token("token-1", 3600));
+    let client = client(&server);
+
+    assert_eq!(client.get_token().unwrap().value, "token-1");
+    // Served from the cache.
+    assert_eq!(client.get_token().unwrap().value, "token-1");
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].rejection, None);
+    let claims = requests[0].claims.as_ref().unwrap();
+    assert_eq!(
+        claims["iss"],
+        "test@example-project.iam.gserviceaccount.com"
+    );
+    assert_eq!(claims["scope"], SCOPE);
+    assert_eq!(claims["aud"], server.token_url().as_str());
+}
+
+#[test]
+fn rejects_assertions_signed_by_another_key() {
+    let server = MockTokenServer::start(
+        Credentials::from_pem(
+            include_str!("../testdata/other_rsa_pkcs1.pem"),
+            "test@example-project.iam.gserviceaccount.com",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let err = client(&server).get_token().unwrap_err();
+    assert!(
+        matches!(&err, Error::OAuth { error, .. } if error == "invalid_grant"),
+        "{}",
+        err
+    );
+    let rejection = server.requests()[0].rejection.clone().unwrap();
+    assert!(rejection.contains("signature"), "{}", rejection);
+}
+
+#[test]
+fn surfaces_oauth_errors() {
+    let server = MockTokenServer::start(credentials()).unwrap();
+    server.respond_with(MockResponse::error(
+        400,
+        "invalid_scope",
+        "The scope is not allowed",
+    ));
+    let err = client(&server).get_token().unwrap_err();
+    assert!(
+        matches!(&err, Error::OAuth { error, .. } if error == "invalid_scope"),
+        "{}",
+        err
+    );
+}